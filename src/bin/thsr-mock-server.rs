@@ -0,0 +1,37 @@
+use clap::Parser;
+use std::process::ExitCode;
+use thsr_ticket_rs::mock_server::{MockServer, Scenario, DEFAULT_CAPTCHA_ANSWER};
+
+/// A local stand-in for the THSR booking site, to point the client at with THSR_BASE_URL
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Port to listen on, or 0 for any free port
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+
+    /// How the sessions go, one after another and then normal: normal, wrong-captcha, sold-out, maintenance, malformed:<booking|trains|passengers|confirmation>
+    #[arg(long = "scenario")]
+    scenarios: Vec<Scenario>,
+
+    /// Answer to the CAPTCHA accepted by the server
+    #[arg(long, default_value = DEFAULT_CAPTCHA_ANSWER)]
+    captcha_answer: String,
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args = Args::parse();
+
+    let server = match MockServer::bind(("127.0.0.1", args.port), args.scenarios, &args.captcha_answer) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Error: failed to listen on port {}: {err}", args.port);
+            return ExitCode::FAILURE;
+        },
+    };
+    println!("Mock server listening on {}", server.base_url());
+    println!("Run the client with THSR_BASE_URL={}", server.base_url());
+    server.wait();
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,239 @@
+use clap::Args;
+use thsr_ticket_rs::dates::DateExpr;
+use thsr_ticket_rs::models::{BookingMethod, CabinClass, Preset, SeatPref, Station, Trip};
+use thsr_ticket_rs::presets::{HumanPreset, Passenger, PassengerType, TicketCounts};
+use thsr_ticket_rs::train_selection::TrainSelectionStrategy;
+use thsr_ticket_rs::utils::to_time_option;
+use thsr_ticket_rs::Result;
+
+/// Booking info given on the command line, which skips the corresponding questions and overrides the preset
+#[derive(Args, Debug, Default, PartialEq, Clone)]
+pub struct BookingArgs {
+    /// Departure station, ex. Taipei, 台北, TPE
+    #[arg(long, help_heading = "Booking")]
+    pub from: Option<Station>,
+
+    /// Destination station, ex. Zuoying, 左營
+    #[arg(long, help_heading = "Booking")]
+    pub to: Option<Station>,
+
+    /// OneWay or RoundTrip [default: RoundTrip if any return-* option is given, otherwise the trip of the preset, or OneWay if --date is given without one]
+    #[arg(long, help_heading = "Booking")]
+    pub trip: Option<Trip>,
+
+    /// Departure date, ex. 2025/01/04, +27d, next friday
+    #[arg(long, value_parser = parse_date, help_heading = "Booking")]
+    pub date: Option<String>,
+
+    /// Departure time in HH:MM, on the hour or half hour of the time table
+    #[arg(long, value_parser = parse_time, conflicts_with = "train", help_heading = "Booking")]
+    pub time: Option<String>,
+
+    /// Book this departure train instead of searching by time
+    #[arg(long, help_heading = "Booking")]
+    pub train: Option<i16>,
+
+    /// Return date, ex. 2025/01/06, next sunday
+    #[arg(long, value_parser = parse_date, help_heading = "Booking")]
+    pub return_date: Option<String>,
+
+    /// Return time in HH:MM, on the hour or half hour of the time table
+    #[arg(long, value_parser = parse_time, conflicts_with = "return_train", help_heading = "Booking")]
+    pub return_time: Option<String>,
+
+    /// Book this return train instead of searching by time
+    #[arg(long, help_heading = "Booking")]
+    pub return_train: Option<i16>,
+
+    /// Pick the train without asking: earliest, fastest, discount, prefer:<train IDs>[/<fallback>] or arrive-by:<HH:MM>
+    #[arg(long, help_heading = "Booking")]
+    pub train_selection: Option<TrainSelectionStrategy>,
+
+    /// Same as --train-selection but for the return train [default: same as --train-selection]
+    #[arg(long, help_heading = "Booking")]
+    pub return_train_selection: Option<TrainSelectionStrategy>,
+
+    /// Standard or Business
+    #[arg(long, help_heading = "Booking")]
+    pub class: Option<CabinClass>,
+
+    /// NoPref, Window or Aisle
+    #[arg(long, help_heading = "Booking")]
+    pub seat: Option<SeatPref>,
+
+    /// Number of adult tickets; the unspecified ticket types default to 0 once any of them is given
+    #[arg(long, help_heading = "Tickets")]
+    pub adult: Option<u8>,
+
+    /// Number of child tickets
+    #[arg(long, help_heading = "Tickets")]
+    pub child: Option<u8>,
+
+    /// Number of disabled tickets
+    #[arg(long, help_heading = "Tickets")]
+    pub disabled: Option<u8>,
+
+    /// Number of elder tickets
+    #[arg(long, help_heading = "Tickets")]
+    pub elder: Option<u8>,
+
+    /// Number of college tickets
+    #[arg(long, help_heading = "Tickets")]
+    pub college: Option<u8>,
+
+    /// Personal ID of the booker
+    #[arg(long, help_heading = "Passengers")]
+    pub id: Option<String>,
+
+    /// Phone number of the booker
+    #[arg(long, help_heading = "Passengers")]
+    pub phone: Option<String>,
+
+    /// Personal ID of a disabled passenger, repeat for each of them
+    #[arg(long, help_heading = "Passengers")]
+    pub disabled_id: Vec<String>,
+
+    /// Personal ID of an elder passenger, repeat for each of them
+    #[arg(long, help_heading = "Passengers")]
+    pub elder_id: Vec<String>,
+}
+
+impl BookingArgs {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The trip to ask about, where a departure date alone means a one-way trip
+    pub fn trip(&self) -> Option<Trip> {
+        self.explicit_trip().or_else(|| self.date.as_ref().map(|_| Trip::OneWay))
+    }
+
+    /// The trip given by --trip or the return-* options, which is all that overrides the trip of a preset
+    fn explicit_trip(&self) -> Option<Trip> {
+        if self.trip.is_some() {
+            self.trip.clone()
+        } else if self.return_date.is_some() || self.return_time.is_some() || self.return_train.is_some() {
+            Some(Trip::RoundTrip)
+        } else {
+            None
+        }
+    }
+
+    pub fn booking_method(&self) -> Option<BookingMethod> {
+        if self.train.is_some() || self.return_train.is_some() {
+            Some(BookingMethod::TrainId)
+        } else if self.time.is_some() || self.return_time.is_some() {
+            Some(BookingMethod::Time)
+        } else {
+            None
+        }
+    }
+
+    /// The ticket numbers if any of them is given
+    pub fn ticket_counts(&self) -> Option<TicketCounts> {
+        let counts = [self.adult, self.child, self.disabled, self.elder, self.college];
+        if counts.iter().all(Option::is_none) {
+            return None;
+        }
+        Some(TicketCounts {
+            adult: self.adult.unwrap_or_default(),
+            child: self.child.unwrap_or_default(),
+            disabled: self.disabled.unwrap_or_default(),
+            elder: self.elder.unwrap_or_default(),
+            college: self.college.unwrap_or_default(),
+        })
+    }
+
+    /// Personal IDs given for the type of passengers
+    pub fn passenger_ids(&self, passenger_type: PassengerType) -> &[String] {
+        match passenger_type {
+            PassengerType::Disabled => &self.disabled_id,
+            PassengerType::Elder => &self.elder_id,
+        }
+    }
+
+    /// Overrides the preset with the values given
+    pub fn apply_to_preset(&self, preset: &Preset) -> Result<Preset> {
+        // The human-readable format keeps passengers by type, which is easier to override than the supplemental IDs
+        let mut human = HumanPreset::from(preset);
+        if let Some(from) = &self.from {
+            human.from = from.clone();
+        }
+        if let Some(to) = &self.to {
+            human.to = to.clone();
+        }
+        if let Some(date) = &self.date {
+            human.date = date.clone();
+        }
+        if let Some(time) = &self.time {
+            human.time = time.clone();
+            human.train = None;
+        }
+        if self.train.is_some() {
+            human.train = self.train;
+        }
+        // A new departure date alone (ex. `--date "next friday"`) keeps the return leg of the preset
+        if let Some(trip) = self.explicit_trip() {
+            if trip == Trip::OneWay {
+                human.return_date = None;
+                human.return_time = None;
+                human.return_train = None;
+            }
+            human.trip = trip;
+        }
+        if self.return_date.is_some() {
+            human.return_date = self.return_date.clone();
+        }
+        if self.return_time.is_some() {
+            human.return_time = self.return_time.clone();
+            human.return_train = None;
+        }
+        if self.return_train.is_some() {
+            human.return_train = self.return_train;
+        }
+        if self.train_selection.is_some() {
+            human.train_selection = self.train_selection.clone();
+        }
+        if self.return_train_selection.is_some() {
+            human.return_train_selection = self.return_train_selection.clone();
+        }
+        if let Some(class) = &self.class {
+            human.class = class.clone();
+        }
+        if let Some(seat) = &self.seat {
+            human.seat = seat.clone();
+        }
+        if let Some(tickets) = self.ticket_counts() {
+            human.tickets = tickets;
+        }
+        if let Some(id) = &self.id {
+            human.personal_id = id.clone();
+        }
+        if let Some(phone) = &self.phone {
+            human.phone = phone.clone();
+        }
+        for (passenger_type, num) in [(PassengerType::Disabled, human.tickets.disabled), (PassengerType::Elder, human.tickets.elder)] {
+            let given = self.passenger_ids(passenger_type);
+            let mut kept = 0;
+            human.passengers.retain(|passenger| {
+                if passenger.passenger_type != passenger_type {
+                    return true;
+                }
+                // Replace them if given, otherwise keep as many as the tickets
+                kept += 1;
+                given.is_empty() && kept <= num
+            });
+            human.passengers.extend(given.iter().map(|id| Passenger { passenger_type, id: id.clone() }));
+        }
+
+        Preset::try_from(&human)
+    }
+}
+
+fn parse_date(date: &str) -> std::result::Result<String, String> {
+    DateExpr::parse(date).map(|_| date.to_string()).map_err(|err| err.to_string())
+}
+
+fn parse_time(time: &str) -> std::result::Result<String, String> {
+    to_time_option(time).map_err(|err| err.to_string())
+}
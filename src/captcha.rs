@@ -0,0 +1,153 @@
+use crate::configs::{CaptchaConfig, CaptchaSolverKind, ImageDisplay};
+use crate::error::{Error, Result};
+use crate::prompter::Prompter;
+use crate::terminal_image::{detect_protocol, render, Protocol};
+use crate::utils::shell_command;
+use log::{debug, warn};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use std::fmt::Debug;
+use std::fs::{create_dir_all, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::Stdio;
+
+pub trait CaptchaSolver: Debug {
+    /// Returns the answer to the CAPTCHA image, asking the prompter if it takes a human
+    fn solve(&mut self, image: &[u8], prompter: &mut dyn Prompter) -> Result<String>;
+}
+
+/// Shows the image inline in the terminal (or in the default image viewer) and asks the prompter for the answer
+#[derive(Debug)]
+pub struct StdinSolver {
+    image_path: PathBuf,
+    display: ImageDisplay,
+    // Keeps stdout clean for the machine-readable output
+    to_stderr: bool,
+}
+
+impl StdinSolver {
+    pub fn new(image_path: impl Into<PathBuf>, display: ImageDisplay, to_stderr: bool) -> Self {
+        Self { image_path: image_path.into(), display, to_stderr }
+    }
+
+    fn show_inline(&self, image: &[u8], protocol: Protocol) -> Result<()> {
+        debug!("showing CAPTCHA inline with {:?}", protocol);
+        let rendered = render(image, protocol)?;
+        if self.to_stderr {
+            let mut stderr = io::stderr();
+            stderr.write_all(rendered.as_bytes())?;
+            stderr.flush()?;
+        } else {
+            let mut stdout = io::stdout();
+            stdout.write_all(rendered.as_bytes())?;
+            stdout.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl CaptchaSolver for StdinSolver {
+    fn solve(&mut self, image: &[u8], prompter: &mut dyn Prompter) -> Result<String> {
+        // Keep a copy of the image in case it can't be shown properly
+        if let Some(parent) = self.image_path.parent() {
+            create_dir_all(parent)?;
+        }
+        File::create(&self.image_path)?.write_all(image)?;
+
+        let protocol = match self.display {
+            ImageDisplay::Auto => Some(detect_protocol()),
+            ImageDisplay::Kitty => Some(Protocol::Kitty),
+            ImageDisplay::Iterm2 => Some(Protocol::Iterm2),
+            ImageDisplay::Sixel => Some(Protocol::Sixel),
+            ImageDisplay::HalfBlock => Some(Protocol::HalfBlock),
+            ImageDisplay::Viewer => None,
+        };
+        match protocol {
+            Some(protocol) => if let Err(err) = self.show_inline(image, protocol) {
+                warn!("failed to show the CAPTCHA inline ({err}), see {}", self.image_path.display());
+            },
+            None => opener::open(&self.image_path).map_err(io::Error::other)?,
+        }
+
+        prompter.ask("Type the answer to the CAPTCHA:")
+    }
+}
+
+/// Pipes the image to the stdin of a shell command and reads the answer from its stdout
+#[derive(Debug)]
+pub struct CommandSolver {
+    command: String,
+}
+
+impl CommandSolver {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self { command: command.into() }
+    }
+}
+
+impl CaptchaSolver for CommandSolver {
+    fn solve(&mut self, image: &[u8], _prompter: &mut dyn Prompter) -> Result<String> {
+        debug!("solving CAPTCHA with command: {}", self.command);
+        let mut child = shell_command(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // Drop stdin once written so the command sees EOF
+        match child.stdin.take().expect("stdin is piped").write_all(image) {
+            // The command may answer without reading the image, ex. a fixed answer for testing
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => debug!("CAPTCHA solver command didn't read the whole image"),
+            result => result?,
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::Io(io::Error::other(format!("CAPTCHA solver command exited with {}", output.status))));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+/// Posts the image to an HTTP endpoint and reads the answer from the response body
+#[derive(Debug)]
+pub struct HttpSolver {
+    url: String,
+    client: Client,
+}
+
+impl HttpSolver {
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            url: url.into(),
+            client: Client::builder().build()?,
+        })
+    }
+}
+
+impl CaptchaSolver for HttpSolver {
+    fn solve(&mut self, image: &[u8], _prompter: &mut dyn Prompter) -> Result<String> {
+        debug!("solving CAPTCHA with endpoint: {}", self.url);
+        let response = self.client.post(&self.url)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(image.to_vec())
+            .send()?
+            .error_for_status()?;
+
+        Ok(response.text()?.trim().to_string())
+    }
+}
+
+/// Builds the solver selected by the config, showing the image on stderr instead of stdout if `to_stderr`
+pub fn build_solver(config: &CaptchaConfig, image_path: &str, to_stderr: bool) -> Result<Box<dyn CaptchaSolver>> {
+    match config.solver {
+        CaptchaSolverKind::Stdin => Ok(Box::new(StdinSolver::new(image_path, config.display.clone(), to_stderr))),
+        CaptchaSolverKind::Command => match &config.command {
+            Some(command) => Ok(Box::new(CommandSolver::new(command))),
+            None => Err(Error::Config("the `command` CAPTCHA solver requires a command".to_string())),
+        },
+        CaptchaSolverKind::Http => match &config.url {
+            Some(url) => Ok(Box::new(HttpSolver::new(url)?)),
+            None => Err(Error::Config("the `http` CAPTCHA solver requires a URL".to_string())),
+        },
+    }
+}
@@ -0,0 +1,416 @@
+use crate::booking_args::BookingArgs;
+use std::collections::HashMap;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use chrono_tz::Tz;
+use log::debug;
+use strum::IntoEnumIterator;
+use thsr_ticket_rs::dates::{latest_bookable_date, today, DateExpr};
+use thsr_ticket_rs::models::{BookingMethod, ParseStationError, BookingPersisted, CabinClass, Preset, SeatPref, Station, TicketConfirmationPersisted, TrainInfo, Trip};
+use thsr_ticket_rs::presets::{PassengerType, TicketCounts};
+use thsr_ticket_rs::prompter::Prompter;
+use thsr_ticket_rs::utils::{format_travel_time, format_date, format_supplement_ids_key};
+use thsr_ticket_rs::{Error, Result};
+
+fn invalid_option(input_str: &str) -> Error {
+    Error::InvalidInput(format!("no such option: {input_str}"))
+}
+
+// Ex. `invalid train ID "8O3": invalid digit found in string`
+fn parse_number<T: FromStr<Err = ParseIntError>>(field: &str, input_str: &str) -> Result<T> {
+    input_str.parse().map_err(|err| Error::InvalidInput(format!("invalid {field} {input_str:?}: {err}")))
+}
+
+pub fn ask_for_string_with_descriptions(prompter: &mut dyn Prompter, descriptions: &str) -> Result<String> {
+    prompter.ask(&format!("Input {descriptions}:"))
+}
+
+pub fn ask_for_string_with_default(prompter: &mut dyn Prompter, descriptions: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(default) if !default.is_empty() => {
+            let input_str = prompter.ask(&format!("Input {descriptions} (default: {default}):"))?;
+            if input_str.is_empty() {
+                Ok(default.to_string())
+            } else {
+                Ok(input_str)
+            }
+        },
+        _ => ask_for_string_with_descriptions(prompter, descriptions),
+    }
+}
+
+pub fn ask_for_station(prompter: &mut dyn Prompter, leg_type: &str, default: Station) -> Result<Station> {
+    // Print all options
+    Station::iter().for_each(|station| {
+        say!("({station_num}) {station_str} {chinese_name}", station_num=station.clone() as u8, station_str=station, chinese_name=station.chinese_name());
+    });
+
+    let input_str = prompter.ask(&format!("Select {leg_type} station by number or name (default: {}):", default.clone() as u8))?;
+    if input_str.is_empty() {
+        Ok(default.clone())
+    } else if let Ok(station_num) = input_str.parse() {
+        Station::from_repr(station_num).ok_or_else(|| invalid_option(&input_str))
+    } else {
+        input_str.parse().map_err(|err: ParseStationError| Error::InvalidInput(err.to_string()))
+    }
+}
+
+pub fn ask_for_date(prompter: &mut dyn Prompter, leg_type: &str, tz: &Tz, default: Option<&str>) -> Result<String> {
+    let today = today(tz);
+    let latest_date = latest_bookable_date(today);
+    let default = default.map(str::to_string).unwrap_or_else(|| format_date(latest_date));
+
+    let input_str = prompter.ask(&format!("Select {leg_type} date ({today}~{latest_date}, or relative ones like +27d, next friday) (default: {default}):", today=format_date(today), latest_date=format_date(latest_date)))?;
+    if input_str.is_empty() {
+        Ok(default)
+    } else {
+        // Keep relative dates as-is so they can be saved in presets, they are resolved when booking
+        let date = DateExpr::parse(&input_str)?;
+        if date.is_fixed() {
+            Ok(format_date(date.resolve(today)?))
+        } else {
+            say!("{input_str} resolves to {}", format_date(date.resolve(today)?));
+            Ok(input_str)
+        }
+    }
+}
+
+// Ex. `2025/01/04`, `next friday (2025/01/10)`
+fn describe_date(date: &str, tz: &Tz) -> String {
+    match DateExpr::parse(date) {
+        Ok(expr) if !expr.is_fixed() => match expr.resolve(today(tz)) {
+            Ok(resolved) => format!("{date} ({})", format_date(resolved)),
+            Err(_) => date.to_string(),
+        },
+        _ => date.to_string(),
+    }
+}
+
+pub fn ask_for_time(prompter: &mut dyn Prompter, leg_type: &str, time_options: &[String], default: Option<&str>) -> Result<String> {
+    // Print all options
+    time_options.iter().enumerate().for_each(|(idx, option)| {
+        let parsed_option = if option.len() == 4 {
+            // Ex. 930A
+            format!("{}:{}", &option[..1], &option[1..])
+        } else {
+            // Ex. 1130A
+            format!("{}:{}", &option[..2], &option[2..])
+        };
+        say!("({option_num}) {parsed_option}", option_num=idx + 1);
+    });
+
+    let default = default
+        .and_then(|default| time_options.iter().position(|option| option == default))
+        .map_or(12, |idx| idx + 1);
+    let input_str = prompter.ask(&format!("Select {leg_type} time (default: {default}):"))?;
+    if input_str.is_empty() {
+        time_options.get(default - 1).cloned().ok_or_else(|| invalid_option(&default.to_string()))
+    } else {
+        parse_number::<usize>(&format!("{leg_type} time option"), &input_str)?
+            .checked_sub(1)
+            .and_then(|idx| time_options.get(idx))
+            .cloned()
+            .ok_or_else(|| invalid_option(&input_str))
+    }
+}
+
+pub fn ask_for_confirmation(prompter: &mut dyn Prompter, descriptions: &str, default: bool) -> Result<bool> {
+    let input_str = prompter.ask(&format!("{descriptions} [{}]:", if default { "Y/n" } else { "y/N" }))?;
+    match input_str.to_lowercase().as_str() {
+        "" => Ok(default),
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err(invalid_option(&input_str)),
+    }
+}
+
+/// Asks whether to save the answers as a new preset and under which name
+pub fn ask_for_new_preset_name(prompter: &mut dyn Prompter) -> Result<Option<Option<String>>> {
+    if !ask_for_confirmation(prompter, "Save these answers as a new preset?", false)? {
+        return Ok(None);
+    }
+    let name = prompter.ask("Input the preset name (default: no name):")?;
+    Ok(Some(Some(name).filter(|name| !name.is_empty())))
+}
+
+pub fn ask_for_trip(prompter: &mut dyn Prompter, default: Trip) -> Result<Trip> {
+    // Print all options
+    Trip::iter().for_each(|option| {
+        say!("({option_num}) {option_str}", option_num=option.clone() as u8, option_str=option);
+    });
+
+    let input_str = prompter.ask(&format!("Select trip type (default: {}):", default.clone() as u8))?;
+    if input_str.is_empty() {
+        Ok(default.clone())
+    } else {
+        Trip::from_repr(parse_number("trip type", &input_str)?).ok_or_else(|| invalid_option(&input_str))
+    }
+}
+
+pub fn ask_for_booking_method(prompter: &mut dyn Prompter, default: BookingMethod) -> Result<BookingMethod> {
+    // Print all options
+    BookingMethod::iter().for_each(|option| {
+        say!("({option_num}) {option_str}", option_num=option.clone() as u8, option_str=option);
+    });
+
+    let input_str = prompter.ask(&format!("Select booking method (default: {}):", default.clone() as u8))?;
+    if input_str.is_empty() {
+        Ok(default.clone())
+    } else {
+        BookingMethod::from_repr(parse_number("booking method", &input_str)?).ok_or_else(|| invalid_option(&input_str))
+    }
+}
+
+pub fn ask_for_train_id(prompter: &mut dyn Prompter, leg_type: &str, default: Option<i16>) -> Result<i16> {
+    match default {
+        Some(default) => {
+            let input_str = prompter.ask(&format!("Input {leg_type} train ID (default: {default}):"))?;
+            if input_str.is_empty() {
+                Ok(default)
+            } else {
+                parse_number(&format!("{leg_type} train ID"), &input_str)
+            }
+        },
+        None => {
+            let input_str = prompter.ask(&format!("Input {leg_type} train ID (ex. 803):"))?;
+            parse_number(&format!("{leg_type} train ID"), &input_str)
+        },
+    }
+}
+
+pub fn ask_for_seat(prompter: &mut dyn Prompter, default: SeatPref) -> Result<SeatPref> {
+    // Print all options
+    SeatPref::iter().for_each(|seat_pref| {
+        say!("({option_num}) {option_str}", option_num=seat_pref.clone() as u8, option_str=seat_pref);
+    });
+
+    let input_str = prompter.ask(&format!("Select seat preference (default: {}):", default.clone() as u8))?;
+    if input_str.is_empty() {
+        Ok(default.clone())
+    } else {
+        SeatPref::from_repr(parse_number("seat preference", &input_str)?).ok_or_else(|| invalid_option(&input_str))
+    }
+}
+
+pub fn ask_for_class(prompter: &mut dyn Prompter, default: CabinClass) -> Result<CabinClass> {
+    // Print all options
+    CabinClass::iter().for_each(|option| {
+        say!("({option_num}) {option_str}", option_num=option.clone() as u8, option_str=option);
+    });
+
+    let input_str = prompter.ask(&format!("Select cabin class (default: {}):", default.clone() as u8))?;
+    if input_str.is_empty() {
+        Ok(default.clone())
+    } else {
+        CabinClass::from_repr(parse_number("cabin class", &input_str)?).ok_or_else(|| invalid_option(&input_str))
+    }
+}
+
+pub fn ask_for_ticket_num(prompter: &mut dyn Prompter, ticket_descriptions: &str, default: u8) -> Result<u8> {
+    let input_str = prompter.ask(&format!("Select number of {ticket_descriptions} tickets (default: {default}):"))?;
+    if input_str.is_empty() {
+        Ok(default)
+    } else {
+        parse_number(&format!("number of {ticket_descriptions} tickets"), &input_str)
+    }
+}
+
+pub fn ask_for_train<'a>(prompter: &mut dyn Prompter, leg_type: &str, trains: &'a [TrainInfo]) -> Result<&'a TrainInfo> {
+    // Print all options
+    say!("Option  Train   Depart  Arrive  Duration  Discount");
+    for (idx, train) in trains.iter().enumerate() {
+        say!("{item_str:<8}{train_id:<8}{train_depart:<8}{train_arrive:<8}{train_travel_time:<10}{train_discount_str}", item_str = format!("({})", idx + 1), train_id = train.id, train_depart = train.depart.format("%H:%M").to_string(), train_arrive = train.arrive.format("%H:%M").to_string(), train_travel_time = format_travel_time(train.travel_time), train_discount_str = train.discounts_str());
+    }
+
+    let input_str = prompter.ask(&format!("Select {leg_type} train (default: 1):"))?;
+    let train_selection = if input_str.is_empty() {
+        0
+    } else {
+        parse_number::<usize>(&format!("{leg_type} train option"), &input_str)?.checked_sub(1).ok_or_else(|| invalid_option(&input_str))?
+    };
+    debug!("Selected {leg_type} option: {}", train_selection + 1);
+
+    trains.get(train_selection).ok_or_else(|| invalid_option(&input_str))
+}
+
+pub fn ask_for_supplement_ids(prompter: &mut dyn Prompter, booking: &BookingPersisted, defaults: Option<&HashMap<String, String>>, given: &BookingArgs) -> Result<HashMap<String, String>> {
+    let mut supplement_ids = HashMap::new();
+    let mut cursor = 0;
+    // Adult tickets does not need supplement IDs
+    cursor += &booking.adult_ticket_num;
+    // Child tickets does not need supplement IDs
+    cursor += &booking.child_ticket_num;
+
+    // Disable tickets
+    for i in 0..booking.disabled_ticket_num {
+        let key = format_supplement_ids_key(cursor + i);
+        let default = defaults.and_then(|defaults| defaults.get(&key)).map(String::as_str);
+        let id = or_ask(given.passenger_ids(PassengerType::Disabled).get(i as usize).cloned(), || {
+            ask_for_string_with_default(prompter, &format!("personal ID for disable ticket #{}", i + 1), default)
+        })?;
+        supplement_ids.insert(key, id);
+    }
+    cursor += &booking.disabled_ticket_num;
+
+    // Elder tickets
+    for i in 0..booking.elder_ticket_num {
+        let key = format_supplement_ids_key(cursor + i);
+        let default = defaults.and_then(|defaults| defaults.get(&key)).map(String::as_str);
+        let id = or_ask(given.passenger_ids(PassengerType::Elder).get(i as usize).cloned(), || {
+            ask_for_string_with_default(prompter, &format!("personal ID for elder ticket #{}", i + 1), default)
+        })?;
+        supplement_ids.insert(key, id);
+    }
+    // cursor += &booking.elder_ticket_num; // Not needed unless there are new types of tickets
+
+    Ok(supplement_ids)
+}
+
+// Only asks if the value isn't given on the command line
+fn or_ask<T>(given: Option<T>, ask: impl FnOnce() -> Result<T>) -> Result<T> {
+    match given {
+        Some(value) => Ok(value),
+        None => ask(),
+    }
+}
+
+// Any option of the time table, which is all the form needs when searching by train ID
+fn any_time_option(time_options: &[String]) -> Result<String> {
+    time_options.first().cloned().ok_or_else(|| Error::layout_changed(r#"select[name="toTimeTable"] > option:not([selected])"#))
+}
+
+/// Asks for the booking info not given on the command line, defaulting to the given worksheet (ex. when editing a preset)
+pub fn ask_for_booking_worksheet(prompter: &mut dyn Prompter, time_options: &[String], tz: &Tz, defaults: Option<&BookingPersisted>, given: &BookingArgs) -> Result<BookingPersisted> {
+    let start_station = or_ask(given.from.clone(), || ask_for_station(prompter, "departure", defaults.map_or(Station::Nangang, |d| d.start_station.clone())))?;
+    let dest_station = or_ask(given.to.clone(), || ask_for_station(prompter, "destination", defaults.map_or(Station::Zuoying, |d| d.dest_station.clone())))?;
+    let types_of_trip = or_ask(given.trip(), || ask_for_trip(prompter, defaults.map_or(Trip::OneWay, |d| d.types_of_trip.clone())))?;
+    let booking_method = or_ask(given.booking_method(), || ask_for_booking_method(prompter, match defaults.and_then(|d| d.to_train_id) {
+        Some(_) => BookingMethod::TrainId,
+        None => BookingMethod::Time,
+    }))?;
+    let outbound_date = or_ask(given.date.clone(), || ask_for_date(prompter, "departure", tz, defaults.map(|d| d.outbound_date.as_str())))?;
+    let (outbound_time, to_train_id) = match booking_method {
+        BookingMethod::Time => (or_ask(given.time.clone(), || ask_for_time(prompter, "departure", time_options, defaults.map(|d| d.outbound_time.as_str())))?, None),
+        // The time table is ignored when searching by train ID, but the form still expects one
+        BookingMethod::TrainId => (any_time_option(time_options)?, Some(or_ask(given.train, || ask_for_train_id(prompter, "departure", defaults.and_then(|d| d.to_train_id)))?)),
+    };
+    let (inbound_date, inbound_time, back_train_id) = match types_of_trip {
+        Trip::OneWay => (None, None, None),
+        Trip::RoundTrip => {
+            let inbound_date = or_ask(given.return_date.clone(), || ask_for_date(prompter, "return", tz, defaults.and_then(|d| d.inbound_date.as_deref())))?;
+            match booking_method {
+                BookingMethod::Time => (Some(inbound_date), Some(or_ask(given.return_time.clone(), || ask_for_time(prompter, "return", time_options, defaults.and_then(|d| d.inbound_time.as_deref())))?), None),
+                BookingMethod::TrainId => (Some(inbound_date), Some(any_time_option(time_options)?), Some(or_ask(given.return_train, || ask_for_train_id(prompter, "return", defaults.and_then(|d| d.back_train_id)))?)),
+            }
+        },
+    };
+    let seat_prefer = or_ask(given.seat.clone(), || ask_for_seat(prompter, defaults.map_or(SeatPref::NoPref, |d| d.seat_prefer.clone())))?;
+    let class_type = or_ask(given.class.clone(), || ask_for_class(prompter, defaults.map_or(CabinClass::Standard, |d| d.class_type.clone())))?;
+    let tickets = or_ask(given.ticket_counts(), || Ok(TicketCounts {
+        adult: ask_for_ticket_num(prompter, "adult", defaults.map_or(1, |d| d.adult_ticket_num))?,
+        child: ask_for_ticket_num(prompter, "child", defaults.map_or(0, |d| d.child_ticket_num))?,
+        disabled: ask_for_ticket_num(prompter, "disabled", defaults.map_or(0, |d| d.disabled_ticket_num))?,
+        elder: ask_for_ticket_num(prompter, "elder", defaults.map_or(0, |d| d.elder_ticket_num))?,
+        college: ask_for_ticket_num(prompter, "college", defaults.map_or(0, |d| d.college_ticket_num))?,
+    }))?;
+
+    Ok(BookingPersisted {
+        start_station,
+        dest_station,
+        types_of_trip,
+        outbound_date,
+        outbound_time,
+        inbound_date,
+        inbound_time,
+        to_train_id,
+        back_train_id,
+        seat_prefer,
+        class_type,
+        adult_ticket_num: tickets.adult,
+        child_ticket_num: tickets.child,
+        disabled_ticket_num: tickets.disabled,
+        elder_ticket_num: tickets.elder,
+        college_ticket_num: tickets.college,
+    })
+}
+
+/// Asks for the passenger info not given on the command line, defaulting to the given worksheet (ex. when editing a preset)
+pub fn ask_for_ticket_confirmation_worksheet(prompter: &mut dyn Prompter, booking: &BookingPersisted, defaults: Option<&TicketConfirmationPersisted>, given: &BookingArgs) -> Result<TicketConfirmationPersisted> {
+    Ok(TicketConfirmationPersisted {
+        personal_id: or_ask(given.id.clone(), || ask_for_string_with_default(prompter, "personal ID", defaults.map(|d| d.personal_id.as_str())))?,
+        phone_num: or_ask(given.phone.clone(), || ask_for_string_with_default(prompter, "phone number", defaults.map(|d| d.phone_num.as_str())))?,
+        supplemental_ids: ask_for_supplement_ids(prompter, booking, defaults.map(|d| &d.supplemental_ids), given)?,
+    })
+}
+
+// TODO I tried to implement a generic `ask_for_enum<T>()` to reduce repetitive codes in `ask_for_seat()`, `ask_for_class()`, etc.;
+//  however, `strum::FromRepr` derive does not provide a trait for building such generic functions.
+//  It has been discussed in https://github.com/Peternator7/strum/issues/251 and there seems to be no solutions yet.
+//
+// ```rust
+// pub fn ask_for_enum<T>(descriptions: &str, default: T) -> Result<T>
+// where
+//     T: IntoEnumIterator + FromRepr + Clone
+// ```
+
+pub fn print_presets(presets: &[Preset], tz: &Tz) {
+    presets.iter().enumerate().for_each(
+        |(idx, preset)| {
+            print_preset(idx + 1, preset, tz)
+        }
+    );
+}
+
+pub fn print_preset(preset_num: usize, preset: &Preset, tz: &Tz) {
+    match &preset.name {
+        Some(name) => say!("Preset #{option_num} ({name})", option_num=preset_num),
+        None => say!("Preset #{option_num}", option_num=preset_num),
+    }
+    say!("  Personal ID:                    {}", preset.ticket_confirmation.personal_id);
+    say!("  Phone:                          {}", preset.ticket_confirmation.phone_num);
+    say!("  Depart Station:                 {:?}", preset.booking.start_station);
+    say!("  Destination Station:            {:?}", preset.booking.dest_station);
+    say!("  Trip:                           {:?}", preset.booking.types_of_trip);
+    say!("  Depart Date:                    {}", describe_date(&preset.booking.outbound_date, tz));
+    match preset.booking.to_train_id {
+        Some(train_id) => say!("  Depart Train:                   {train_id}"),
+        None => say!("  Depart Time:                    {}", preset.booking.outbound_time),
+    }
+    if preset.booking.types_of_trip == Trip::RoundTrip {
+        say!("  Return Date:                    {}", describe_date(preset.booking.inbound_date.as_deref().unwrap_or_default(), tz));
+        match preset.booking.back_train_id {
+            Some(train_id) => say!("  Return Train:                   {train_id}"),
+            None => say!("  Return Time:                    {}", preset.booking.inbound_time.as_deref().unwrap_or_default()),
+        }
+    }
+    if let Some(strategy) = &preset.train_selection {
+        say!("  Train Selection:                {strategy}");
+    }
+    if let Some(strategy) = &preset.return_train_selection {
+        say!("  Return Train Selection:         {strategy}");
+    }
+    say!("  Cabin Class:                    {:?}", preset.booking.class_type);
+    say!("  Seat Preference:                {:?}", preset.booking.seat_prefer);
+    say!("  Adult ticket number:            {}", preset.booking.adult_ticket_num);
+    say!("  Child ticket number:            {}", preset.booking.child_ticket_num);
+    say!("  Disabled ticket number:         {}", preset.booking.disabled_ticket_num);
+    say!("  Elder ticket number:            {}", preset.booking.elder_ticket_num);
+    say!("  College ticket number:          {}", preset.booking.college_ticket_num);
+
+    // Print supplemental personal IDs
+    let mut cursor = 0;
+    // Skip adult & child tickets (no personal ID needed)
+    cursor += preset.booking.adult_ticket_num + preset.booking.child_ticket_num;
+    // Disabled tickets
+    for i in 0..preset.booking.disabled_ticket_num {
+        say!("  Disabled ticket #{} personal ID: {}", i + 1, preset.ticket_confirmation.supplemental_ids.get(&format_supplement_ids_key(cursor + i)).map(String::as_str).unwrap_or_default());
+    }
+    cursor += preset.booking.disabled_ticket_num;
+    // Elder tickets
+    for i in 0..preset.booking.elder_ticket_num {
+        say!("  Elder ticket #{} personal ID:    {}", i + 1, preset.ticket_confirmation.supplemental_ids.get(&format_supplement_ids_key(cursor + i)).map(String::as_str).unwrap_or_default());
+    }
+    // cursor += preset.booking.elder_ticket_num; // Not needed unless there are new types of tickets
+
+    say!();
+}
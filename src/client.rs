@@ -0,0 +1,145 @@
+use crate::clock::ClockSample;
+use crate::configs::Endpoints;
+use crate::error::{Error, Result};
+use crate::dates::today;
+use crate::models::{Booking, BookingFormParams, BookingResponse, BookingResult, TicketConfirmation, TicketConfirmationFormParams, TrainSelection};
+use crate::parsers::{parse_booking_page, parse_confirmation_page, parse_passengers_page, parse_trains_page};
+use crate::utils::gen_common_headers;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz::Asia__Taipei;
+use log::debug;
+use reqwest::header::DATE;
+use reqwest::blocking::Client;
+use reqwest::redirect::Policy;
+
+/// Client of the THSR booking site.
+///
+/// The booking flow goes through the following steps, each of which maps to a page of the site:
+/// 1. [`ThsrClient::start_session`] and [`ThsrClient::fetch_captcha`]: the booking page (S1)
+/// 2. [`ThsrClient::submit_booking`]: the train list (S2)
+/// 3. [`ThsrClient::submit_train_selection`]: the passenger form (S3)
+/// 4. [`ThsrClient::submit_ticket_confirmation`]: the booking results
+///
+/// The session is kept in the cookie store of the underlying HTTP client, so the steps must be done with the same client.
+#[derive(Debug)]
+pub struct ThsrClient {
+    client: Client,
+    endpoints: Endpoints,
+}
+
+impl ThsrClient {
+    /// Creates a client of the THSR booking site
+    pub fn new() -> Result<Self> {
+        Self::with_endpoints(Endpoints::default())
+    }
+
+    /// Creates a client of the site at the endpoints, ex. a stand-in server for testing
+    pub fn with_endpoints(endpoints: Endpoints) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder()
+                .redirect(Policy::default())
+                .cookie_store(true)
+                .build()?,
+            endpoints,
+        })
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    /// Starts a new session and collects the parameters required by the booking form
+    pub fn start_session(&self) -> Result<BookingFormParams> {
+        let response = self.client
+            .get(self.endpoints.booking_page_url())
+            .headers(gen_common_headers(&self.endpoints))
+            .send()?
+            .error_for_status()?;
+
+        // Find session ID
+        let session_id = response.cookies()
+            .find(|cookie| cookie.name() == "JSESSIONID")
+            .ok_or_else(|| Error::MissingCookie("JSESSIONID".to_string()))?
+            .value().to_string();
+
+        let response_text = response.text()?;
+        debug!("booking page: {:?}", response_text);
+        parse_booking_page(&response_text, session_id, &self.endpoints)
+    }
+
+    /// Times a request to the site against its `Date` header, or returns `None` if the header is missing or malformed
+    pub fn sample_clock(&self) -> Result<Option<ClockSample>> {
+        let sent = Utc::now();
+        let response = self.client
+            .head(self.endpoints.base_url())
+            .headers(gen_common_headers(&self.endpoints))
+            .send()?;
+        let received = Utc::now();
+
+        let server_date = response.headers().get(DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok());
+        debug!("server date: {:?}, sent at {sent}, received at {received}", server_date);
+        Ok(server_date.map(|server_date| ClockSample {
+            sent,
+            received,
+            server_date: server_date.with_timezone(&Utc),
+        }))
+    }
+
+    /// Downloads the CAPTCHA image of the session
+    pub fn fetch_captcha(&self, booking_form_params: &BookingFormParams) -> Result<Vec<u8>> {
+        let response = self.client
+            .get(&booking_form_params.captcha_url)
+            .headers(gen_common_headers(&self.endpoints))
+            .send()?
+            .error_for_status()?;
+        Ok(response.bytes()?.to_vec())
+    }
+
+    /// Submits the booking form and returns either the available trains or, if the server already determined the train, the passenger form parameters
+    pub fn submit_booking(&self, session_id: &str, booking: &Booking) -> Result<BookingResponse> {
+        // Submit booking info
+        let url = self.endpoints.submit_booking_url(session_id);
+        debug!("submit_booking_form_url: {}", url);
+        let response = self.client.post(url)
+            .headers(gen_common_headers(&self.endpoints))
+            .form(&booking)
+            .send()?
+            .error_for_status()?;
+        debug!("submit booking response: {:?}", response);
+        let response_text = response.text()?;
+        debug!("submit booking response text: {:?}", response_text);
+        // The server may go straight to the passenger form if the train is already determined (ex. searching by train ID)
+        parse_trains_page(&response_text)
+    }
+
+    /// Submits the selected train(s) and returns the passenger form parameters
+    pub fn submit_train_selection(&self, train_selection: &TrainSelection) -> Result<TicketConfirmationFormParams> {
+        // Submit train selection info
+        let response = self.client.post(self.endpoints.submit_train_url())
+            .headers(gen_common_headers(&self.endpoints))
+            .form(&train_selection)
+            .send()?
+            .error_for_status()?;
+        debug!("submit train selection response: {:?}", response);
+        let response_text = response.text()?;
+        debug!("submit train selection response text: {:?}", response_text);
+        parse_passengers_page(&response_text)
+    }
+
+    /// Submits the passenger info and returns the booked ticket
+    pub fn submit_ticket_confirmation(&self, ticket_confirmation: &TicketConfirmation) -> Result<BookingResult> {
+        // Submit ticket confirmation
+        let response = self.client.post(self.endpoints.submit_ticket_confirmation_url())
+            .headers(gen_common_headers(&self.endpoints))
+            .form(&ticket_confirmation)
+            .send()?
+            .error_for_status()?;
+        debug!("submit ticket confirmation response: {:?}", response);
+        let response_text = response.text()?;
+        debug!("submit ticket confirmation response text: {:?}", response_text);
+        // The confirmation page only tells the month and day of the travel dates
+        parse_confirmation_page(&response_text, today(&Asia__Taipei))
+    }
+}
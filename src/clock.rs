@@ -0,0 +1,59 @@
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use std::thread::sleep;
+
+// The `Date` header only has a resolution of one second
+const DATE_RESOLUTION_MS: i64 = 1000;
+
+// Sleeping is only as accurate as the scheduler, so the last moment is spent spinning
+const SPIN_THRESHOLD_MS: i64 = 20;
+
+/// A request to the server timed by the local clock
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClockSample {
+    /// Local time when the request was sent
+    pub sent: DateTime<Utc>,
+    /// Local time when the response was received
+    pub received: DateTime<Utc>,
+    /// The `Date` header of the response
+    pub server_date: DateTime<Utc>,
+}
+
+/// Estimates how far the server clock is ahead of the local clock.
+///
+/// The server read its clock sometime between sending and receiving, and truncated it to the second,
+/// so each sample bounds the offset; samples taken at different fractions of a second narrow it down further.
+pub fn estimate_offset(samples: &[ClockSample]) -> Option<Duration> {
+    let resolution = Duration::milliseconds(DATE_RESOLUTION_MS);
+    let bounds: Vec<(Duration, Duration)> = samples.iter()
+        .map(|sample| (sample.server_date - sample.received, sample.server_date + resolution - sample.sent))
+        .collect();
+    let lower = bounds.iter().map(|(lower, _)| *lower).max()?;
+    let upper = bounds.iter().map(|(_, upper)| *upper).min()?;
+    debug!("clock offset between {lower} and {upper}");
+    if lower <= upper {
+        return Some(lower + (upper - lower) / 2);
+    }
+
+    // The bounds contradict each other (ex. the server clock was adjusted), so take the median of the samples instead
+    let mut midpoints: Vec<Duration> = bounds.iter().map(|(lower, upper)| *lower + (*upper - *lower) / 2).collect();
+    midpoints.sort();
+    Some(midpoints[midpoints.len() / 2])
+}
+
+/// Blocks until the local clock reaches the time
+pub fn sleep_until(time: DateTime<Utc>) {
+    loop {
+        let remaining = time - Utc::now();
+        if remaining <= Duration::zero() {
+            return;
+        }
+        if remaining > Duration::milliseconds(SPIN_THRESHOLD_MS) {
+            // Wake up a bit early and re-check, as long sleeps may overshoot
+            let nap = remaining - Duration::milliseconds(SPIN_THRESHOLD_MS);
+            sleep(nap.to_std().unwrap_or_default());
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
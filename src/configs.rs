@@ -1,13 +1,145 @@
-macro_rules! constants {
-    ($base_url:expr) => {
-        pub const CAPTCHA_LOCAL_PATH: &str = "tmp/captcha.png";
-        pub const PRESETS_PATH: &str = ".db/presets.json";
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use reqwest::Url;
+use std::fs::File;
+use std::io::{self, BufReader};
+use strum::{Display, EnumString};
 
-        pub const BASE_URL: &str = $base_url;
-        pub const BOOKING_PAGE_URL: &str = concat!($base_url, "/IMINT/?locale=tw");
-        pub const SUBMIT_TRAIN_URL: &str = concat!($base_url, "/IMINT/?wicket:interface=:1:BookingS2Form::IFormSubmitListener");
-        pub const SUBMIT_TICKET_CONFIRMATION_URL: &str = concat!($base_url, "/IMINT/?wicket:interface=:2:BookingS3Form::IFormSubmitListener");
-    };
+pub const CAPTCHA_LOCAL_PATH: &str = "tmp/captcha.png";
+pub const PRESETS_PATH: &str = ".db/presets.json";
+pub const CONFIG_PATH: &str = ".db/config.json";
+pub const HISTORY_PATH: &str = ".db/history.jsonl";
+
+pub const DEFAULT_BASE_URL: &str = "https://irs.thsrc.com.tw";
+
+/// URLs of the booking site, all under the base URL, so the client can be pointed at a stand-in server or a proxy path
+#[derive(Debug, PartialEq, Clone)]
+pub struct Endpoints {
+    base_url: String,
+    // Host and port, ex. `irs.thsrc.com.tw`, `localhost:8080`
+    host: String,
+}
+
+impl Endpoints {
+    pub fn new(base_url: &str) -> Result<Self> {
+        let base_url = base_url.trim().trim_end_matches('/');
+        let invalid = |message: &str| Error::Config(format!("invalid base URL {base_url:?}: {message}"));
+        let url = Url::parse(base_url).map_err(|err| invalid(&err.to_string()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(invalid("expecting http or https"));
+        }
+        let host = url.host_str().ok_or_else(|| invalid("missing the host"))?;
+        Ok(Self {
+            host: match url.port() {
+                Some(port) => format!("{host}:{port}"),
+                None => host.to_string(),
+            },
+            base_url: base_url.to_string(),
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Resolves an absolute path on the site, ex. the `src` of the CAPTCHA image
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    pub fn booking_page_url(&self) -> String {
+        self.url("/IMINT/?locale=tw")
+    }
+
+    pub fn submit_booking_url(&self, session_id: &str) -> String {
+        self.url(&format!("/IMINT/;jsessionid={session_id}?wicket:interface=:0:BookingS1Form::IFormSubmitListener"))
+    }
+
+    pub fn submit_train_url(&self) -> String {
+        self.url("/IMINT/?wicket:interface=:1:BookingS2Form::IFormSubmitListener")
+    }
+
+    pub fn submit_ticket_confirmation_url(&self) -> String {
+        self.url("/IMINT/?wicket:interface=:2:BookingS3Form::IFormSubmitListener")
+    }
 }
 
-constants!("https://irs.thsrc.com.tw");
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self::new(DEFAULT_BASE_URL).expect("the default base URL is valid")
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Config {
+    #[serde(default)]
+    pub captcha: CaptchaConfig,
+    // Base URL of the booking site, ex. a stand-in server for testing or a proxy path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+}
+
+impl Config {
+    /// Loads the config file, or falls back to the default config if the file doesn't exist
+    pub fn load(path: &str) -> Result<Self> {
+        match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|err| Error::Config(format!("failed to parse {path}: {err}"))),
+            Err(err) => match err.kind() {
+                io::ErrorKind::NotFound => Ok(Self::default()),
+                _ => Err(err.into()),
+            },
+        }
+    }
+
+    pub fn endpoints(&self) -> Result<Endpoints> {
+        Endpoints::new(self.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct CaptchaConfig {
+    #[serde(default)]
+    pub solver: CaptchaSolverKind,
+    // Shell command for the `command` solver
+    #[serde(default)]
+    pub command: Option<String>,
+    // Endpoint for the `http` solver
+    #[serde(default)]
+    pub url: Option<String>,
+    // How the `stdin` solver shows the image
+    #[serde(default)]
+    pub display: ImageDisplay,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, EnumString, Display, Clone)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CaptchaSolverKind {
+    // Show the image and ask on stdin
+    #[default]
+    Stdin,
+    // Pipe the image to a shell command and read the answer from its stdout
+    Command,
+    // Post the image to an HTTP endpoint and read the answer from the response body
+    Http,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, EnumString, Display, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum ImageDisplay {
+    // Inline in the terminal with the best protocol detected
+    #[default]
+    Auto,
+    Kitty,
+    Iterm2,
+    Sixel,
+    HalfBlock,
+    // In the default image viewer of the desktop
+    Viewer,
+}
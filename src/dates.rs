@@ -0,0 +1,171 @@
+use crate::error::{Error, Result};
+use crate::models::BookingPersisted;
+use crate::utils::format_date;
+use chrono::{Datelike, Days, Duration, NaiveDate, Utc, Weekday};
+use chrono_tz::Tz;
+
+// Tickets can be booked up to this many days ahead (including today)
+pub const BOOKABLE_DAYS: i64 = 30;
+
+/// Today in the given timezone
+pub fn today(tz: &Tz) -> NaiveDate {
+    Utc::now().with_timezone(tz).date_naive()
+}
+
+/// The latest date whose tickets are on sale
+pub fn latest_bookable_date(today: NaiveDate) -> NaiveDate {
+    today + Duration::days(BOOKABLE_DAYS)
+}
+
+/// A date given either as-is or relative to today, so presets don't go stale after one use.
+///
+/// Supported expressions (case-insensitive):
+/// - `2025/01/04`: the date itself
+/// - `today`, `tomorrow`, `latest` or `latest bookable`
+/// - Any of the above followed by offsets in days or weeks, ex. `+27d`, `latest-1w`, `today+28` (days by default)
+/// - `next friday`: the first Friday after today
+/// - `first weekday after today+28`: the first day matching `weekday`, `weekend` or a day name after the date
+#[derive(Debug, PartialEq, Clone)]
+pub enum DateExpr {
+    Fixed(NaiveDate),
+    Today,
+    LatestBookable,
+    Offset {
+        base: Box<DateExpr>,
+        days: i64,
+    },
+    FirstAfter {
+        day: DayMatcher,
+        base: Box<DateExpr>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DayMatcher {
+    Day(Weekday),
+    // Monday to Friday
+    Weekday,
+    // Saturday and Sunday
+    Weekend,
+}
+
+impl DayMatcher {
+    fn matches(&self, date: NaiveDate) -> bool {
+        let weekday = date.weekday();
+        match self {
+            DayMatcher::Day(day) => weekday == *day,
+            DayMatcher::Weekday => !matches!(weekday, Weekday::Sat | Weekday::Sun),
+            DayMatcher::Weekend => matches!(weekday, Weekday::Sat | Weekday::Sun),
+        }
+    }
+}
+
+impl DateExpr {
+    pub fn parse(expr: &str) -> Result<Self> {
+        parse_date_expr(&expr.trim().to_lowercase())
+            .map_err(|message| Error::InvalidInput(format!("invalid date {expr:?}: {message}")))
+    }
+
+    /// The date relative to today, failing if it's beyond the dates chrono supports
+    pub fn resolve(&self, today: NaiveDate) -> Result<NaiveDate> {
+        match self {
+            DateExpr::Fixed(date) => Ok(*date),
+            DateExpr::Today => Ok(today),
+            DateExpr::LatestBookable => Ok(latest_bookable_date(today)),
+            DateExpr::Offset { base, days } => {
+                let base = base.resolve(today)?;
+                Duration::try_days(*days)
+                    .and_then(|offset| base.checked_add_signed(offset))
+                    .ok_or_else(|| Error::InvalidInput(format!("invalid date: {days} days from {} is out of range", format_date(base))))
+            },
+            DateExpr::FirstAfter { day, base } => {
+                let base = base.resolve(today)?;
+                // Every kind of day shows up within a week
+                (1..=7).filter_map(|days| base.checked_add_days(Days::new(days)))
+                    .find(|date| day.matches(*date))
+                    .ok_or_else(|| Error::InvalidInput(format!("invalid date: the week after {} is out of range", format_date(base))))
+            },
+        }
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        matches!(self, DateExpr::Fixed(_))
+    }
+}
+
+fn parse_date_expr(expr: &str) -> std::result::Result<DateExpr, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(expr, "%Y/%m/%d") {
+        return Ok(DateExpr::Fixed(date));
+    }
+    if let Some(rest) = expr.strip_prefix("first ") {
+        let (day, base) = rest.split_once(" after ").ok_or("expecting `first <day> after <date>`")?;
+        return Ok(DateExpr::FirstAfter {
+            day: parse_day_matcher(day.trim())?,
+            base: Box::new(parse_date_expr(base.trim())?),
+        });
+    }
+    if let Some(day) = expr.strip_prefix("next ") {
+        return Ok(DateExpr::FirstAfter {
+            day: parse_day_matcher(day.trim())?,
+            base: Box::new(DateExpr::Today),
+        });
+    }
+
+    // A base date followed by offsets, ex. `today+28`, `+27d`
+    let (base, offsets) = expr.split_at(expr.find(['+', '-']).unwrap_or(expr.len()));
+    let base = match base.trim() {
+        "" | "today" => DateExpr::Today,
+        "tomorrow" => DateExpr::Offset { base: Box::new(DateExpr::Today), days: 1 },
+        "latest" | "latest bookable" => DateExpr::LatestBookable,
+        base => return Err(format!("unknown date {base:?}")),
+    };
+    if offsets.is_empty() {
+        return Ok(base);
+    }
+    Ok(DateExpr::Offset { base: Box::new(base), days: parse_offsets(offsets)? })
+}
+
+// Ex. `+27d`, `+1w-2d`
+fn parse_offsets(offsets: &str) -> std::result::Result<i64, String> {
+    let offsets: String = offsets.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut days: i64 = 0;
+    let mut rest = offsets.as_str();
+    while let Some(sign) = rest.chars().next() {
+        let sign = match sign {
+            '+' => 1,
+            '-' => -1,
+            _ => return Err(format!("expecting + or - before {rest:?}")),
+        };
+        rest = &rest[1..];
+        let digits_len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let num: i64 = rest[..digits_len].parse().map_err(|_| format!("expecting a number in {offsets:?}"))?;
+        rest = &rest[digits_len..];
+        let unit = match rest.chars().next() {
+            Some('d') => { rest = &rest[1..]; 1 },
+            Some('w') => { rest = &rest[1..]; 7 },
+            _ => 1,
+        };
+        days = num.checked_mul(sign * unit)
+            .and_then(|offset| days.checked_add(offset))
+            .ok_or_else(|| format!("offset {offsets:?} is too large"))?;
+    }
+    Ok(days)
+}
+
+fn parse_day_matcher(day: &str) -> std::result::Result<DayMatcher, String> {
+    match day {
+        "weekday" => Ok(DayMatcher::Weekday),
+        "weekend" => Ok(DayMatcher::Weekend),
+        day => day.parse().map(DayMatcher::Day).map_err(|_| format!("unknown day {day:?}")),
+    }
+}
+
+/// Resolves the date expressions of the booking info to the dates expected by the booking form
+pub fn resolve_booking_dates(booking: &BookingPersisted, today: NaiveDate) -> Result<BookingPersisted> {
+    let resolve = |expr: &str| DateExpr::parse(expr).and_then(|date| date.resolve(today)).map(format_date);
+    Ok(BookingPersisted {
+        outbound_date: resolve(&booking.outbound_date)?,
+        inbound_date: booking.inbound_date.as_deref().map(resolve).transpose()?,
+        ..booking.clone()
+    })
+}
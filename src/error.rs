@@ -0,0 +1,96 @@
+use crate::models::ErrorMessages;
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to communicate with the booking site
+    Network(reqwest::Error),
+    /// The response lacks the named cookie
+    MissingCookie(String),
+    /// The page doesn't look like what we expect, most likely because the site has changed
+    LayoutChanged {
+        selector: String,
+    },
+    /// The site rejected the submission with feedback messages
+    ServerFeedback(ErrorMessages),
+    /// The user input can't be understood
+    InvalidInput(String),
+    /// The presets can't be loaded or don't contain what was asked for
+    Preset(String),
+    /// The config is malformed or incomplete
+    Config(String),
+    /// None of the available trains is acceptable by the train selection strategy
+    NoMatchingTrain(String),
+    /// Local file or terminal I/O failed
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn layout_changed(selector: &str) -> Self {
+        Error::LayoutChanged { selector: selector.to_string() }
+    }
+
+    /// Whether the server rejected the submission because of a wrong answer to the CAPTCHA
+    pub fn is_wrong_captcha(&self) -> bool {
+        matches!(self, Error::ServerFeedback(messages) if messages.is_wrong_captcha())
+    }
+
+    /// Whether the server rejected the submission because there are no tickets left, which may change later
+    pub fn is_sold_out(&self) -> bool {
+        matches!(self, Error::ServerFeedback(messages) if messages.is_sold_out())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network(err) => write!(f, "network error: {err}"),
+            Error::MissingCookie(name) => write!(f, "cookie {name} not found in the response"),
+            Error::LayoutChanged { selector } => write!(f, "page layout changed: nothing matches `{selector}`"),
+            Error::ServerFeedback(messages) => write!(f, "rejected by the server: {messages}"),
+            Error::InvalidInput(message) => write!(f, "invalid input: {message}"),
+            Error::Preset(message) => write!(f, "preset error: {message}"),
+            Error::Config(message) => write!(f, "config error: {message}"),
+            Error::NoMatchingTrain(message) => write!(f, "no matching train: {message}"),
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(err) => Some(err),
+            Error::ServerFeedback(messages) => Some(messages),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Network(err)
+    }
+}
+
+impl From<ErrorMessages> for Error {
+    fn from(messages: ErrorMessages) -> Self {
+        Error::ServerFeedback(messages)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<chrono::ParseError> for Error {
+    fn from(err: chrono::ParseError) -> Self {
+        Error::InvalidInput(err.to_string())
+    }
+}
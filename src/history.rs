@@ -0,0 +1,78 @@
+use crate::error::Result;
+use crate::models::{BookingPersisted, BookingResult, Station};
+use crate::presets::{display_fromstr, TicketCounts};
+use crate::utils::parse_date;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// A successful booking, saved as a line of JSON in the history file
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub booked_at: DateTime<FixedOffset>,
+    // Name of the preset used, or its number at the time if it's unnamed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(with = "display_fromstr")]
+    pub from: Station,
+    #[serde(with = "display_fromstr")]
+    pub to: Station,
+    pub date: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_date: Option<NaiveDate>,
+    pub tickets: TicketCounts,
+    pub result: BookingResult,
+}
+
+impl HistoryEntry {
+    /// Records the booking submitted, whose dates must have been resolved
+    pub fn new(booked_at: DateTime<FixedOffset>, preset: Option<String>, booking: &BookingPersisted, result: BookingResult) -> Result<Self> {
+        Ok(Self {
+            booked_at,
+            preset,
+            from: booking.start_station.clone(),
+            to: booking.dest_station.clone(),
+            date: parse_date(&booking.outbound_date)?,
+            return_date: booking.inbound_date.as_deref().map(parse_date).transpose()?,
+            tickets: TicketCounts::from(booking),
+            result,
+        })
+    }
+}
+
+/// Appends the entry to the history file, creating it if it doesn't exist
+pub fn append_history(path: &str, entry: &HistoryEntry) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+    line.push('\n');
+    // A single write keeps the line whole even if other processes are appending too
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Loads the history from the oldest to the latest, or returns an empty history if the file doesn't exist
+pub fn load_history(path: &str) -> Result<Vec<HistoryEntry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut entries = Vec::new();
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // One broken line (ex. a crash halfway writing it) shouldn't hide the rest of the history
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => warn!("skipped line {} of {path}: {err}", idx + 1),
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,72 @@
+use crate::render::render_history_entry;
+use chrono::NaiveDate;
+use chrono_tz::Tz::Asia__Taipei;
+use clap::Args;
+use thsr_ticket_rs::configs;
+use thsr_ticket_rs::dates::{today, DateExpr};
+use thsr_ticket_rs::history::{load_history, HistoryEntry};
+use thsr_ticket_rs::models::Station;
+use thsr_ticket_rs::Result;
+
+/// Filters of the past bookings, all of which must match
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    /// Path to the history file
+    #[arg(long, default_value = configs::HISTORY_PATH)]
+    history_file: String,
+
+    /// Booking with this PNR
+    #[arg(long)]
+    pnr: Option<String>,
+
+    /// Departing from this station
+    #[arg(long)]
+    from: Option<Station>,
+
+    /// Heading to this station
+    #[arg(long)]
+    to: Option<Station>,
+
+    /// Departing on or after this date, ex. 2025/01/04, today
+    #[arg(long, value_parser = DateExpr::parse)]
+    since: Option<DateExpr>,
+
+    /// Departing on or before this date, ex. 2025/01/31, today+7
+    #[arg(long, value_parser = DateExpr::parse)]
+    until: Option<DateExpr>,
+
+    /// Booked with this preset, by its name (or number if unnamed)
+    #[arg(long)]
+    preset: Option<String>,
+
+    /// Only the latest N bookings
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+pub fn run_history_command(args: &HistoryArgs) -> Result<()> {
+    let today = today(&Asia__Taipei);
+    let since = args.since.as_ref().map(|date| date.resolve(today)).transpose()?;
+    let until = args.until.as_ref().map(|date| date.resolve(today)).transpose()?;
+
+    let entries: Vec<HistoryEntry> = load_history(&args.history_file)?.into_iter()
+        .filter(|entry| args.matches(entry, since, until))
+        .collect();
+    if entries.is_empty() {
+        println!("No matching bookings in {}", args.history_file);
+    }
+    let skipped = entries.len().saturating_sub(args.limit.unwrap_or(entries.len()));
+    entries[skipped..].iter().for_each(|entry| println!("{}", render_history_entry(entry)));
+    Ok(())
+}
+
+impl HistoryArgs {
+    fn matches(&self, entry: &HistoryEntry, since: Option<NaiveDate>, until: Option<NaiveDate>) -> bool {
+        self.pnr.as_ref().is_none_or(|pnr| *pnr == entry.result.pnr)
+            && self.from.as_ref().is_none_or(|from| *from == entry.from)
+            && self.to.as_ref().is_none_or(|to| *to == entry.to)
+            && since.is_none_or(|since| entry.date >= since)
+            && until.is_none_or(|until| entry.date <= until)
+            && self.preset.as_ref().is_none_or(|preset| entry.preset.as_ref() == Some(preset))
+    }
+}
@@ -0,0 +1,19 @@
+pub mod captcha;
+pub mod client;
+pub mod clock;
+pub mod configs;
+pub mod dates;
+pub mod error;
+pub mod history;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+pub mod models;
+pub mod parsers;
+pub mod presets;
+pub mod prompter;
+pub mod terminal_image;
+pub mod train_selection;
+pub mod utils;
+
+pub use client::ThsrClient;
+pub use error::{Error, Result};
@@ -1,88 +1,238 @@
-mod configs;
-mod models;
-mod utils;
-
-use crate::models::{Booking, BookingFormParams, BookingPersisted, CabinClass, Preset, SeatPref, Station, TicketConfirmation, TicketConfirmationFormParams, TicketConfirmationPersisted, TrainInfo, TrainSelection};
-use crate::utils::{ask_for_class, ask_for_date, ask_for_seat, ask_for_station, ask_for_string_with_descriptions, ask_for_supplement_ids, ask_for_ticket_num, ask_for_time, assert_submission_errors, format_date, gen_booking, gen_booking_url, gen_common_headers, gen_ticket_confirmation, parse_discount, print_preset, print_presets};
+// Declared first for the `say!` macro to be available to the other modules
+#[macro_use]
+mod render;
+mod booking_args;
+mod cli;
+mod history_command;
+mod presets_command;
+mod prompt_args;
+mod schedule;
+mod watch;
+
+use crate::booking_args::BookingArgs;
+use crate::cli::{ask_for_booking_worksheet, ask_for_new_preset_name, ask_for_ticket_confirmation_worksheet, ask_for_train, print_preset, print_presets};
+use crate::history_command::{run_history_command, HistoryArgs};
+use crate::presets_command::{run_presets_command, PresetsCommand};
+use crate::prompt_args::PromptArgs;
+use crate::render::{emit, finish, set_output_format, Event, OutputFormat};
+use crate::schedule::{run_book_at, ScheduleArgs};
+use crate::watch::{run_watch, WatchArgs};
+use chrono::{NaiveDate, Utc};
 use chrono_tz::Tz;
 use chrono_tz::Tz::Asia__Taipei;
-use clap::{arg, Parser};
-use log::debug;
-use opener;
-use reqwest::blocking::Client;
-use reqwest::redirect::Policy;
-use scraper::{Element, Html, Selector};
-use std::error::Error;
-use std::io::BufReader;
-use std::path::Path;
-use std::{fs::create_dir_all, fs::File, io::{self, Write}};
+use clap::{Parser, Subcommand};
+use log::{debug, info, warn};
+use std::process::ExitCode;
+use thsr_ticket_rs::captcha::{build_solver, CaptchaSolver};
+use thsr_ticket_rs::configs;
+use thsr_ticket_rs::configs::{CaptchaSolverKind, Config, ImageDisplay};
+use thsr_ticket_rs::dates::{resolve_booking_dates, today};
+use thsr_ticket_rs::history::{append_history, HistoryEntry};
+use thsr_ticket_rs::models::{AvailableTrains, Booking, BookingFormParams, BookingPersisted, BookingResponse, BookingResult, Preset, TicketConfirmation, TicketConfirmationFormParams, TicketConfirmationPersisted, TrainInfo, TrainSelection};
+use thsr_ticket_rs::presets::{append_preset, find_preset, load_presets};
+use thsr_ticket_rs::prompter::Prompter;
+use thsr_ticket_rs::train_selection::TrainSelectionStrategy;
+use thsr_ticket_rs::utils::{default_time_options, format_travel_time, gen_booking, gen_ticket_confirmation};
+use thsr_ticket_rs::{Error, Result, ThsrClient};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Auto-select preset #
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    book: BookArgs,
+}
+
+/// Options of booking, shared by the commands going through the booking flow
+#[derive(clap::Args, Debug)]
+struct BookArgs {
+    /// Path to the presets file
+    #[arg(long, default_value = configs::PRESETS_PATH)]
+    presets_file: String,
+
+    /// Path to the history file, where successful bookings are recorded
+    #[arg(long, default_value = configs::HISTORY_PATH)]
+    history_file: String,
+
+    /// Output format: text, json (a single document at the end) or jsonl (an event per line as it happens)
+    #[arg(long, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Auto-select the preset by its number or name
     #[arg(short, long)]
-    preset: Option<usize>,
+    preset: Option<String>,
+
+    /// Max number of attempts to answer the CAPTCHA
+    #[arg(long, default_value_t = 3)]
+    captcha_attempts: u32,
+
+    /// Path to the config file
+    #[arg(long, default_value = configs::CONFIG_PATH)]
+    config: String,
+
+    #[command(flatten)]
+    prompt: PromptArgs,
+
+    /// Base URL of the booking site, ex. a stand-in server for testing or a proxy path (overrides the config file)
+    #[arg(long, env = "THSR_BASE_URL")]
+    base_url: Option<String>,
+
+    /// How to solve the CAPTCHA: stdin, command or http (overrides the config file)
+    #[arg(long)]
+    captcha_solver: Option<CaptchaSolverKind>,
+
+    /// Shell command for the `command` CAPTCHA solver; the image is piped to its stdin and the answer read from its stdout
+    #[arg(long)]
+    captcha_command: Option<String>,
+
+    /// Endpoint for the `http` CAPTCHA solver; the image is posted to it and the answer read from the response body
+    #[arg(long)]
+    captcha_url: Option<String>,
+
+    /// How the `stdin` CAPTCHA solver shows the image: auto, kitty, iterm2, sixel, half-block or viewer (overrides the config file)
+    #[arg(long)]
+    captcha_display: Option<ImageDisplay>,
+
+    #[command(flatten)]
+    booking: BookingArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage presets
+    Presets {
+        /// Path to the presets file
+        #[arg(long, global = true, default_value = configs::PRESETS_PATH)]
+        presets_file: String,
+
+        #[command(flatten)]
+        prompt: PromptArgs,
+
+        #[command(subcommand)]
+        command: PresetsCommand,
+    },
+    /// Book tickets, optionally at a scheduled time (the same as without a command if --at isn't given)
+    Book {
+        #[command(flatten)]
+        book: BookArgs,
+
+        #[command(flatten)]
+        schedule: ScheduleArgs,
+    },
+    /// List past bookings
+    History {
+        #[command(flatten)]
+        args: HistoryArgs,
+    },
+    /// Search repeatedly until a matching train is available, then book it or notify
+    Watch {
+        #[command(flatten)]
+        book: BookArgs,
+
+        #[command(flatten)]
+        watch: WatchArgs,
+    },
 }
 
 #[derive(Debug)]
 struct App {
-    args: Args,
-    client: Client,
+    args: BookArgs,
+    config: Config,
+    client: ThsrClient,
+    captcha_solver: Box<dyn CaptchaSolver>,
+    prompter: Box<dyn Prompter>,
     tz: Tz,
+    // Recorded in the history along with the bookings
+    preset_label: Option<String>,
+    // Relative dates are resolved against this day instead of today if set
+    booking_day: Option<NaiveDate>,
     booking_worksheet: Option<BookingPersisted>,
     ticket_confirmation_worksheet: Option<TicketConfirmationPersisted>,
+    // Asks for the train if not set
+    train_selection: Option<TrainSelectionStrategy>,
+    return_train_selection: Option<TrainSelectionStrategy>,
 }
 
 impl App {
-    fn new() -> Result<Self, Box<dyn Error>> {
+    fn new(args: BookArgs) -> Result<Self> {
+        set_output_format(args.output);
+
+        // Command-line options take precedence over the config file
+        let mut config = Config::load(&args.config)?;
+        if let Some(base_url) = &args.base_url {
+            config.base_url = Some(base_url.clone());
+        }
+        if let Some(solver) = &args.captcha_solver {
+            config.captcha.solver = solver.clone();
+        }
+        if let Some(command) = &args.captcha_command {
+            config.captcha.command = Some(command.clone());
+        }
+        if let Some(url) = &args.captcha_url {
+            config.captcha.url = Some(url.clone());
+        }
+        if let Some(display) = &args.captcha_display {
+            config.captcha.display = display.clone();
+        }
+        debug!("config: {:?}", config);
+
+        // Fail up front rather than at the first CAPTCHA
+        if args.prompt.unattended && config.captcha.solver == CaptchaSolverKind::Stdin {
+            return Err(Error::Config("--unattended needs a CAPTCHA solver other than stdin".to_string()));
+        }
+        // The questions and the CAPTCHA image go along with the other messages
+        let to_stderr = args.output != OutputFormat::Text;
+        let prompter = args.prompt.build_prompter(to_stderr)?;
+
         Ok(Self {
-            args: Args::parse(),
-            client: Client::builder()
-                .redirect(Policy::default())
-                .cookie_store(true)
-                .build()?,
+            client: ThsrClient::with_endpoints(config.endpoints()?)?,
+            captcha_solver: build_solver(&config.captcha, configs::CAPTCHA_LOCAL_PATH, to_stderr)?,
+            prompter,
             tz: Asia__Taipei,
+            preset_label: None,
+            booking_day: None,
             booking_worksheet: None,
             ticket_confirmation_worksheet: None,
+            train_selection: args.booking.train_selection.clone(),
+            return_train_selection: args.booking.return_train_selection.clone(),
+            args,
+            config,
         })
     }
 
-    fn prepare_preset(&mut self) -> Result<(), Box<dyn Error>> {
+    fn prepare_preset(&mut self) -> Result<()> {
         // Load presets
-        let presets = match File::open(configs::PRESETS_PATH) {
-            Ok(file) => Ok(serde_json::from_reader::<_, Vec<Preset>>(BufReader::new(file))?),
-            Err(err) => match err.kind() {
-                io::ErrorKind::NotFound => {
-                    println!("Presets not found in {}, skip", configs::PRESETS_PATH);
-                    Ok(Vec::new())
-                },
-                _ => Err(err)
+        let presets = match load_presets(&self.args.presets_file)? {
+            Some(presets) => presets,
+            None => {
+                say!("Presets not found in {}, skip", self.args.presets_file);
+                Vec::new()
             },
-        }?;
+        };
 
-        match self.args.preset {
-            Some(preset_num) => {
+        match &self.args.preset {
+            Some(key) => {
                 // Load the preset if specified
-                println!("Auto-select preset:");
-                let preset = &presets[preset_num - 1];
-                print_preset(preset_num, preset);
-                self.load_preset(preset);
+                say!("Auto-select preset:");
+                let (idx, preset) = find_preset(&presets, key)?;
+                print_preset(idx + 1, preset, &self.tz);
+                self.load_preset(idx + 1, preset)?;
             },
             None => {
                 // Otherwise, only ask user for it if available
-                if presets.len() > 0 {
+                if !presets.is_empty() {
                     // Ask for preset selection
-                    print_presets(&presets);
+                    print_presets(&presets, &self.tz);
 
-                    println!("Select the preset to load (default: ask for new info):");
-                    let mut preset_idx_str = String::new();
-                    io::stdin().read_line(&mut preset_idx_str)?;
-                    let preset_idx_str_trimmed = preset_idx_str.trim().to_string();
+                    let preset_idx_str_trimmed = self.prompter.ask("Select the preset to load by number or name (default: ask for new info):")?;
 
                     // If user selected a preset
-                    if preset_idx_str_trimmed.len() > 0 {
-                        self.load_preset(&presets[preset_idx_str_trimmed.parse::<usize>()? - 1]);
+                    if !preset_idx_str_trimmed.is_empty() {
+                        let (idx, preset) = find_preset(&presets, &preset_idx_str_trimmed)?;
+                        self.load_preset(idx + 1, preset)?;
                     }
                 }
             }
@@ -91,261 +241,300 @@ impl App {
         Ok(())
     }
 
-    fn load_preset(&mut self, preset: &Preset) {
-        self.booking_worksheet = Some(preset.booking.clone());
-        self.ticket_confirmation_worksheet = Some(preset.ticket_confirmation.clone());
+    fn load_preset(&mut self, preset_num: usize, preset: &Preset) -> Result<()> {
+        // Numbers change as presets are removed, so prefer the name
+        self.preset_label = Some(preset.name.clone().unwrap_or_else(|| preset_num.to_string()));
+        // Values given on the command line take precedence
+        let preset = self.args.booking.apply_to_preset(preset)?;
+        self.booking_worksheet = Some(preset.booking);
+        self.ticket_confirmation_worksheet = Some(preset.ticket_confirmation);
+        self.train_selection = preset.train_selection;
+        self.return_train_selection = preset.return_train_selection;
+        Ok(())
     }
 
-    fn start_session_with_captcha(&mut self) -> Result<BookingFormParams, Box<dyn Error>> {
-        let response = self.client
-            .get(configs::BOOKING_PAGE_URL)
-            .headers(gen_common_headers())
-            .send()?;
-
-        // Find session ID
-        let session_id = response.cookies().find(|cookie| cookie.name() == "JSESSIONID").unwrap().value().to_string();
-
-        let response_text = response.text()?;
-        let document = Html::parse_document(&response_text);
-
-        // Find all essential parameters
-        let search_by_time_value = document.select(&Selector::parse(r#"input[name="bookingMethod"][data-target="search-by-time"]"#).unwrap()).next().unwrap().value().attr("value").unwrap().to_string();
-        debug!("search-by-time parameter: {search_by_time_value}");
-        let time_options: Vec<String> = document
-            .select(&Selector::parse(r#"select[name="toTimeTable"] > option:not([selected])"#).unwrap())
-            .map(|elem| {
-                elem.value().attr("value").unwrap().to_string()
-            })
-            .collect();
-        debug!("time_options: {:?}", time_options);
-
-        // Show CAPTCHA image
-        let selector = Selector::parse("#BookingS1Form_homeCaptcha_passCode").unwrap();
-        let element = document.select(&selector).next().expect("Couldn't find the captcha element");
-        let src = element.value().attr("src").expect("Couldn't find the captcha source url");
-        let captcha_url = [configs::BASE_URL, src].concat();
-        // Download and open image
-        let response = self.client.get(captcha_url).headers(gen_common_headers()).send()?;
-        let bytes = response.bytes()?;
-        let path = Path::new(configs::CAPTCHA_LOCAL_PATH);
-        create_dir_all(path.parent().unwrap())?;
-        let mut file = File::create(path)?;
-        file.write_all(&bytes)?;
-        opener::open(path)?;
-
-        Ok(BookingFormParams{
-            session_id,
-            search_by_time_value,
-            time_options,
-        })
+    /// Asks for the booking and passenger info missing from the preset up front, instead of in the middle of booking
+    fn prepare_worksheets(&mut self) -> Result<()> {
+        if self.booking_worksheet.is_none() {
+            // The time options are the same every day, the site won't be asked until it's time
+            let booking_worksheet = ask_for_booking_worksheet(self.prompter.as_mut(), &default_time_options(), &self.tz, None, &self.args.booking)?;
+            self.booking_worksheet = Some(booking_worksheet);
+        }
+        if self.ticket_confirmation_worksheet.is_none() {
+            let booking_worksheet = self.booking_worksheet.clone().expect("booking worksheet is prepared above");
+            let ticket_confirmation_worksheet = ask_for_ticket_confirmation_worksheet(self.prompter.as_mut(), &booking_worksheet, None, &self.args.booking)?;
+            self.offer_to_save_preset(&booking_worksheet, &ticket_confirmation_worksheet)?;
+            self.ticket_confirmation_worksheet = Some(ticket_confirmation_worksheet);
+        }
+        Ok(())
     }
 
-    fn solve_captcha(&mut self) -> Result<String, Box<dyn Error>> {
-        println!("Type the answer to the CAPTCHA: ");
-        let mut captcha_solution = String::new();
-        io::stdin().read_line(&mut captcha_solution)?;
+    /// Starts a new session and submits the booking info, returning the available trains
+    fn search_trains(&mut self) -> Result<(Booking, BookingResponse)> {
+        let (session_id, booking) = self.prepare_search()?;
 
-        Ok(captcha_solution.trim().to_string())
+        // Submit booking and get available trains
+        self.submit_booking_with_captcha_retries(session_id, booking)
     }
 
-    fn prepare_booking(&mut self, booking_form_params: &BookingFormParams, captcha_solution: String) -> Result<Booking, Box<dyn Error>> {
-        match &self.booking_worksheet {
-            // Preset exists
-            Some(booking_worksheet) => Ok(gen_booking(
-                booking_worksheet,
-                booking_form_params,
-                captcha_solution,
-            )),
-            // No preset, ask the user for more info
-            None => Ok(gen_booking(
-                &BookingPersisted {
-                    start_station: ask_for_station("departure", Station::Nangang)?,
-                    dest_station: ask_for_station("destination", Station::Zuouing)?,
-                    outbound_date: format_date(ask_for_date("departure", &self.tz)?),
-                    outbound_time: ask_for_time("departure", booking_form_params)?,
-                    seat_prefer: ask_for_seat(SeatPref::NoPref)?,
-                    class_type: ask_for_class(CabinClass::Standard)?,
-                    adult_ticket_num: ask_for_ticket_num("adult", 1)?,
-                    child_ticket_num: ask_for_ticket_num("child", 0)?,
-                    disabled_ticket_num: ask_for_ticket_num("disabled", 0)?,
-                    elder_ticket_num: ask_for_ticket_num("elder", 0)?,
-                    college_ticket_num: ask_for_ticket_num("college", 0)?,
-                },
-                booking_form_params,
-                captcha_solution,
-            )),
+    /// Starts a new session and fills in the booking form, returning the session ID and the booking ready to submit
+    fn prepare_search(&mut self) -> Result<(String, Booking)> {
+        let (booking_form_params, captcha_image) = self.start_session_with_captcha()?;
+        debug!("JSESSIONID: {}", booking_form_params.session_id);
+
+        // Get the answer to the CAPTCHA
+        let captcha_solution = self.solve_captcha(&captcha_image)?;
+        debug!("CAPTCHA solution entered: {}", captcha_solution);
+
+        // Prepare booking info
+        let booking = self.prepare_booking(&booking_form_params, captcha_solution)?;
+        debug!("booking: {:?}", booking);
+        debug!("booking (json): {}", serde_json::to_string(&booking).unwrap());
+
+        Ok((booking_form_params.session_id, booking))
+    }
+
+    /// Selects the train(s) and submits the passenger info in the session of the search
+    fn complete_booking(&mut self, booking: Booking, booking_response: BookingResponse) -> Result<BookingResult> {
+        let ticket_confirmation_form_params = match booking_response {
+            BookingResponse::Trains(trains) => {
+                debug!("trains: {:?}", trains);
+                emit(Event::TrainsFound { outbound: &trains.outbound, inbound: &trains.inbound });
+
+                // Select train
+                let train_selection = self.select_train(trains)?;
+                debug!("train_selection: {:?}", train_selection);
+                debug!("train_selection (json): {}", serde_json::to_string(&train_selection).unwrap());
+
+                // Submit train selection
+                self.submit_train_selection(&train_selection)?
+            },
+            BookingResponse::TrainSelected(ticket_confirmation_form_params) => {
+                debug!("train selected by the server, skip train selection");
+                ticket_confirmation_form_params
+            },
+        };
+
+        // Prepare ticket info
+        let ticket_confirmation = self.prepare_ticket_confirmation(&ticket_confirmation_form_params, &booking)?;
+        debug!("ticket_confirmation: {:?}", ticket_confirmation);
+        debug!("ticket_confirmation (json): {}", serde_json::to_string(&ticket_confirmation).unwrap());
+
+        // Submit ticket info
+        emit(Event::Submitted);
+        let booking_result = self.submit_ticket_confirmation(ticket_confirmation)?;
+        debug!("booking_result: {:?}", booking_result);
+        self.record_booking(&booking, &booking_result);
+        emit(Event::Booked { result: &booking_result });
+        Ok(booking_result)
+    }
+
+    fn record_booking(&self, booking: &Booking, booking_result: &BookingResult) {
+        let booked_at = Utc::now().with_timezone(&self.tz).fixed_offset();
+        let recorded = HistoryEntry::new(booked_at, self.preset_label.clone(), &booking.persisted, booking_result.clone())
+            .and_then(|entry| append_history(&self.args.history_file, &entry));
+        // The tickets are booked anyway, so don't fail over the record
+        if let Err(err) = recorded {
+            warn!("failed to record the booking in {}: {err}", self.args.history_file);
         }
     }
 
-    fn submit_booking_and_get_trains(&self, session_id: String, booking: &Booking) -> Result<Vec<TrainInfo>, Box<dyn Error>> {
-        // Submit booking info
-        let url = gen_booking_url(session_id);
-        debug!("submit_booking_form_url: {}", url);
-        let response = self.client.post(url)
-            .headers(gen_common_headers())
-            .form(&booking)
-            .send()?;
-        debug!("submit booking response: {:?}", response);
-        let response_text = response.text()?;
-        debug!("submit booking response text: {:?}", response_text);
-        assert_submission_errors(response_text.clone())?;
-
-        // Parse train info
-        let document = Html::parse_document(&response_text);
-        let trains = document
-            .select(&Selector::parse("label").unwrap())
-            .map(|label| {
-                TrainInfo {
-                    id: label.select(&Selector::parse("#QueryCode").unwrap()).next().unwrap().inner_html().parse().unwrap(),
-                    depart: label.select(&Selector::parse("#QueryDeparture").unwrap()).next().unwrap().inner_html(),
-                    arrive: label.select(&Selector::parse("#QueryArrival").unwrap()).next().unwrap().inner_html(),
-                    travel_time: label.select(&Selector::parse(".duration > span:nth-of-type(2)").unwrap()).next().unwrap().inner_html(),
-                    discount_str: parse_discount(label),
-                    form_value: label.select(&Selector::parse(r#"input[name="TrainQueryDataViewPanel:TrainGroup"]"#).unwrap()).next().unwrap().value().attr("value").unwrap().to_string(),
-                }
-            })
-            .collect();
+    fn start_session_with_captcha(&mut self) -> Result<(BookingFormParams, Vec<u8>)> {
+        let booking_form_params = self.client.start_session()?;
+        emit(Event::SessionStarted);
+
+        // Download CAPTCHA image
+        let captcha_image = self.client.fetch_captcha(&booking_form_params)?;
+        emit(Event::CaptchaFetched);
 
-        Ok(trains)
+        Ok((booking_form_params, captcha_image))
     }
 
-    fn select_train(&self, trains: Vec<TrainInfo>) -> Result<TrainSelection, Box<dyn Error>> {
-        println!("Option  Train   Depart  Arrive  Duration  Discount");
-        for (idx, train) in trains.iter().enumerate() {
-            println!("{item_str:<8}{train_id:<8}{train_depart:<8}{train_arrive:<8}{train_travel_time:<10}{train_discount_str}", item_str = format!("({})", idx + 1), train_id = train.id, train_depart = train.depart, train_arrive = train.arrive, train_travel_time = train.travel_time, train_discount_str = train.discount_str);
+    fn solve_captcha(&mut self, captcha_image: &[u8]) -> Result<String> {
+        self.captcha_solver.solve(captcha_image, self.prompter.as_mut())
+    }
+
+    fn prepare_booking(&mut self, booking_form_params: &BookingFormParams, captcha_solution: String) -> Result<Booking> {
+        let booking_worksheet = match &self.booking_worksheet {
+            // Preset exists
+            Some(booking_worksheet) => booking_worksheet.clone(),
+            // No preset, ask the user for more info and keep the answers as they are for saving as a preset
+            None => {
+                let booking_worksheet = ask_for_booking_worksheet(self.prompter.as_mut(), &booking_form_params.time_options, &self.tz, None, &self.args.booking)?;
+                self.booking_worksheet = Some(booking_worksheet.clone());
+                booking_worksheet
+            },
+        };
+        // The dates may be relative to today
+        Ok(gen_booking(
+            &resolve_booking_dates(&booking_worksheet, self.booking_day.unwrap_or_else(|| today(&self.tz)))?,
+            booking_form_params,
+            captcha_solution,
+        ))
+    }
+
+    fn submit_booking_and_get_trains(&self, session_id: String, booking: &Booking) -> Result<BookingResponse> {
+        self.client.submit_booking(&session_id, booking)
+    }
+
+    fn submit_booking_with_captcha_retries(&mut self, session_id: String, mut booking: Booking) -> Result<(Booking, BookingResponse)> {
+        let mut session_id = session_id;
+        let mut attempt = 1;
+        loop {
+            match self.submit_booking_and_get_trains(session_id, &booking) {
+                Err(err) if err.is_wrong_captcha() && attempt < self.args.captcha_attempts => {
+                    attempt += 1;
+                    say!("Wrong answer to the CAPTCHA, try again ({attempt}/{})", self.args.captcha_attempts);
+                    emit(Event::CaptchaRejected { attempt: attempt - 1 });
+
+                    // Get a fresh CAPTCHA and resubmit the same booking with the new answer
+                    let (booking_form_params, captcha_image) = self.start_session_with_captcha()?;
+                    debug!("JSESSIONID: {}", booking_form_params.session_id);
+                    let captcha_solution = self.solve_captcha(&captcha_image)?;
+                    debug!("CAPTCHA solution entered: {}", captcha_solution);
+                    booking = gen_booking(&booking.persisted, &booking_form_params, captcha_solution);
+                    session_id = booking_form_params.session_id;
+                },
+                result => return Ok((booking, result?)),
+            }
         }
-        println!("Enter selection (default: 1): ");
-        let mut train_selection_str = String::new();
-        io::stdin().read_line(&mut train_selection_str)?;
-        let trimmed_input = train_selection_str.trim();
-        let train_selection = if trimmed_input.is_empty() {
-            0
+    }
+
+    fn select_train(&mut self, trains: AvailableTrains) -> Result<TrainSelection> {
+        let selected_train = select_train_of_leg(self.prompter.as_mut(), "departure", &trains.outbound, self.train_selection.as_ref())?;
+        let selected_back_train = if trains.inbound.is_empty() {
+            None
         } else {
-            trimmed_input.parse::<usize>().unwrap() - 1
+            let strategy = self.return_train_selection.as_ref().or(self.train_selection.as_ref());
+            Some(select_train_of_leg(self.prompter.as_mut(), "return", &trains.inbound, strategy)?)
         };
-        debug!("Selected option: {}", train_selection + 1);
 
         Ok(TrainSelection {
-            selected_train: trains[train_selection].form_value.clone(),
+            selected_train,
+            selected_back_train,
             form_mark: String::from(""),
         })
     }
 
-    fn submit_train_selection(&self, train_selection: &TrainSelection, booking: &Booking) -> Result<TicketConfirmation, Box<dyn Error>> {
-        // Submit train selection info
-        let response = self.client.post(configs::SUBMIT_TRAIN_URL)
-            .headers(gen_common_headers())
-            .form(&train_selection)
-            .send()?;
-        debug!("submit train selection response: {:?}", response);
-        let response_text = response.text()?;
-        debug!("submit train selection response text: {:?}", response_text);
-        assert_submission_errors(response_text.clone())?;
-
-        let document = Html::parse_document(&response_text);
-        let ticket_confirmation_form_params = TicketConfirmationFormParams {
-            member_value: document
-                .select(&Selector::parse(r#"input[name="TicketMemberSystemInputPanel:TakerMemberSystemDataView:memberSystemRadioGroup"][checked]"#).unwrap())
-                .next().unwrap().value().attr("value").unwrap().to_string(),
-        };
+    fn submit_train_selection(&self, train_selection: &TrainSelection) -> Result<TicketConfirmationFormParams> {
+        self.client.submit_train_selection(train_selection)
+    }
 
+    fn prepare_ticket_confirmation(&mut self, ticket_confirmation_form_params: &TicketConfirmationFormParams, booking: &Booking) -> Result<TicketConfirmation> {
         match &self.ticket_confirmation_worksheet {
             // Preset exists
             Some(ticket_confirmation_worksheet) => Ok(gen_ticket_confirmation(
                 ticket_confirmation_worksheet,
-                &ticket_confirmation_form_params,
+                ticket_confirmation_form_params,
             )),
             // No preset, ask the user for more info
-            None => Ok(gen_ticket_confirmation(
-                &TicketConfirmationPersisted {
-                    personal_id: ask_for_string_with_descriptions("personal ID")?,
-                    phone_num: ask_for_string_with_descriptions("phone number")?,
-                    supplemental_ids: ask_for_supplement_ids(booking)?,
-                },
-                &ticket_confirmation_form_params,
-            ))
+            None => {
+                let ticket_confirmation_worksheet = ask_for_ticket_confirmation_worksheet(self.prompter.as_mut(), &booking.persisted, None, &self.args.booking)?;
+                // Prefer the answers with relative dates unresolved
+                let booking_worksheet = self.booking_worksheet.clone().unwrap_or_else(|| booking.persisted.clone());
+                self.offer_to_save_preset(&booking_worksheet, &ticket_confirmation_worksheet)?;
+                Ok(gen_ticket_confirmation(
+                    &ticket_confirmation_worksheet,
+                    ticket_confirmation_form_params,
+                ))
+            },
         }
     }
 
-    fn submit_ticket_confirmation(&self, ticket_confirmation: TicketConfirmation) -> Result<(), Box<dyn Error>> {
-        // Submit ticket confirmation
-        let response = self.client.post(configs::SUBMIT_TICKET_CONFIRMATION_URL)
-            .headers(gen_common_headers())
-            .form(&ticket_confirmation)
-            .send()?;
-        debug!("submit ticket confirmation response: {:?}", response);
-        let response_text = response.text()?;
-        debug!("submit ticket confirmation response text: {:?}", response_text);
-        assert_submission_errors(response_text.clone())?;
-
-        // Parse ticket
-        let document = Html::parse_document(&response_text);
-        println!("\n\n----------- Booking Results -----------");
-        println!("Ticket ID: {}", document.select(&Selector::parse("p.pnr-code > span:first-child").unwrap()).next().unwrap().inner_html());
-        println!("Total price: {}", document.select(&Selector::parse("#setTrainTotalPriceValue").unwrap()).next().unwrap().inner_html());
-        println!("---------------------------------------");
-        println!("Date    From   Dest    Depart  Arrive  Train");
-        println!(
-            "{:<8}{:<6}{:<6}{:<8}{:<8}{:<8}",
-            document.select(&Selector::parse("span.date > span").unwrap()).next().unwrap().inner_html(),
-            document.select(&Selector::parse("p.departure-stn > span").unwrap()).next().unwrap().inner_html(),
-            document.select(&Selector::parse("p.arrival-stn > span").unwrap()).next().unwrap().inner_html(),
-            document.select(&Selector::parse("#setTrainDeparture0").unwrap()).next().unwrap().inner_html(),
-            document.select(&Selector::parse("#setTrainArrival0").unwrap()).next().unwrap().inner_html(),
-            document.select(&Selector::parse("#setTrainCode0").unwrap()).next().unwrap().inner_html(),
-        );
-        let seat_class = document.select(&Selector::parse("p.info-title").unwrap())
-            .find(|elem| { elem.inner_html() == "車廂" }).unwrap()
-            .next_sibling_element().unwrap()
-            .select(&Selector::parse("span").unwrap())
-            .next().unwrap().inner_html();
-        document.select(&Selector::parse("div.seat-label > span").unwrap())
-            .for_each(|elem| {
-                println!("{seat_class} {}", elem.inner_html());
-            });
-
+    fn offer_to_save_preset(&mut self, booking_worksheet: &BookingPersisted, ticket_confirmation_worksheet: &TicketConfirmationPersisted) -> Result<()> {
+        // Don't get in the way of automation
+        if !self.prompter.is_interactive() {
+            return Ok(());
+        }
+        let Some(name) = ask_for_new_preset_name(self.prompter.as_mut())? else {
+            return Ok(());
+        };
+        let preset = Preset {
+            name,
+            booking: booking_worksheet.clone(),
+            ticket_confirmation: ticket_confirmation_worksheet.clone(),
+            train_selection: self.train_selection.clone(),
+            return_train_selection: self.return_train_selection.clone(),
+        };
+        // Failing to save the preset shouldn't stop the booking
+        match append_preset(&self.args.presets_file, preset) {
+            Ok(preset_num) => say!("Saved as preset #{preset_num} in {}", self.args.presets_file),
+            Err(err) => warn!("failed to save the preset: {err}"),
+        }
         Ok(())
     }
-}
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // Control logging level through env var `RUST_LOG`
-    env_logger::init();
-
-    // Start a new session
-    let mut app = App::new()?;
-    debug!("app inited: {:?}", app);
-
-    app.prepare_preset()?;
+    fn submit_ticket_confirmation(&self, ticket_confirmation: TicketConfirmation) -> Result<BookingResult> {
+        self.client.submit_ticket_confirmation(&ticket_confirmation)
+    }
+}
 
-    let booking_form_params = app.start_session_with_captcha()?;
-    debug!("JSESSIONID: {}", booking_form_params.session_id);
+/// Picks the train by the strategy, or asks the user if there isn't one
+fn select_train_of_leg(prompter: &mut dyn Prompter, leg_type: &str, trains: &[TrainInfo], strategy: Option<&TrainSelectionStrategy>) -> Result<String> {
+    let train = match strategy {
+        Some(strategy) => {
+            let train = strategy.select(trains)?;
+            // Leave a trace of why this train was booked for unattended bookings
+            let discounts = if train.discounts.is_empty() { String::new() } else { format!(", {}", train.discounts_str()) };
+            say!("Selected {leg_type} train {} ({} -> {}, {}{discounts}) by {strategy}", train.id, train.depart.format("%H:%M"), train.arrive.format("%H:%M"), format_travel_time(train.travel_time));
+            info!("selected {leg_type} train {:?} by {strategy}", train);
+            train
+        },
+        None => ask_for_train(prompter, leg_type, trains)?,
+    };
+    emit(Event::TrainSelected { leg: leg_type, train, strategy: strategy.map(ToString::to_string) });
+    Ok(train.form_value.clone())
+}
 
-    // Get user input for CAPTCHA
-    let captcha_solution = app.solve_captcha()?;
-    debug!("CAPTCHA solution entered: {}", captcha_solution);
+fn exit_code(err: &Error) -> u8 {
+    match err {
+        Error::Io(_) => 1,
+        Error::InvalidInput(_) => 2,
+        Error::Preset(_) => 3,
+        Error::Network(_) => 4,
+        Error::ServerFeedback(_) => 5,
+        Error::LayoutChanged { .. } => 6,
+        Error::MissingCookie(_) => 7,
+        Error::Config(_) => 8,
+        Error::NoMatchingTrain(_) => 9,
+    }
+}
 
-    // Prepare booking info
-    let booking = app.prepare_booking(&booking_form_params, captcha_solution)?;
-    debug!("booking: {:?}", booking);
-    debug!("booking (json): {}", serde_json::to_string(&booking).unwrap());
+fn main() -> ExitCode {
+    // Control logging level through env var `RUST_LOG`
+    env_logger::init();
 
-    // Submit booking and get available trains
-    let trains = app.submit_booking_and_get_trains(booking_form_params.session_id, &booking)?;
-    debug!("trains: {:?}", trains);
+    let exit = match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            emit(Event::Failed { message: err.to_string(), exit_code: exit_code(&err) });
+            ExitCode::from(exit_code(&err))
+        },
+    };
+    finish();
+    exit
+}
 
-    // Select train
-    let train_selection = app.select_train(trains)?;
-    debug!("train_selection: {:?}", train_selection);
-    debug!("train_selection (json): {}", serde_json::to_string(&train_selection).unwrap());
+fn run(args: Args) -> Result<()> {
+    match args.command {
+        Some(Command::Presets { presets_file, prompt, command }) => run_presets_command(&presets_file, command, prompt.build_prompter(false)?.as_mut()),
+        Some(Command::Book { book, schedule }) => match schedule.at {
+            Some(at) => run_book_at(App::new(book)?, at, &schedule),
+            None => run_book(App::new(book)?),
+        },
+        Some(Command::History { args }) => run_history_command(&args),
+        Some(Command::Watch { book, watch }) => run_watch(App::new(book)?, &watch),
+        None => run_book(App::new(args.book)?),
+    }
+}
 
-    // Submit train selection and prepare ticket info
-    let ticket_confirmation = app.submit_train_selection(&train_selection, &booking)?;
-    debug!("ticket_confirmation: {:?}", ticket_confirmation);
-    debug!("ticket_confirmation (json): {}", serde_json::to_string(&ticket_confirmation).unwrap());
+fn run_book(mut app: App) -> Result<()> {
+    debug!("app inited: {:?}", app);
+    app.prepare_preset()?;
 
-    // Submit train selection and prepare ticket info
-    app.submit_ticket_confirmation(ticket_confirmation)?;
+    let (booking, booking_response) = app.search_trains()?;
+    app.complete_booking(booking, booking_response)?;
 
     Ok(())
 }
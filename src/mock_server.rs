@@ -0,0 +1,647 @@
+//! A local stand-in for the THSR booking site, for exercising the booking flow offline.
+//!
+//! It serves the four pages the client goes through with the same selectors and form keys as the site:
+//! the booking page (S1) with its CAPTCHA and `JSESSIONID`, the train list (S2), the passenger form (S3)
+//! and the confirmation page. Point the client at [`MockServer::base_url`] to use it.
+//!
+//! Each session (starting from the booking page) plays the next [`Scenario`] of the script, and then
+//! [`Scenario::Normal`] once the script runs out, ex. `[WrongCaptcha, Normal]` rejects the first answer to the CAPTCHA only.
+//! The requests are recorded for checking what was posted.
+
+use crate::models::Station;
+use chrono::{NaiveDate, NaiveTime, Utc};
+use image::{ImageBuffer, ImageFormat, Rgb};
+use log::{debug, warn};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use strum::{Display, EnumString};
+
+pub const DEFAULT_CAPTCHA_ANSWER: &str = "ABCD";
+
+const SEARCH_BY_TIME_VALUE: &str = "radio31";
+const SEARCH_BY_TRAIN_ID_VALUE: &str = "radio33";
+const MEMBER_RADIO_VALUE: &str = "radio44";
+const CAPTCHA_PATH: &str = "/IMINT/?wicket:interface=:0:BookingS1Form:homeCaptcha:passCode::IResourceListener";
+// Feedback of the site, the first of which is recognized as a wrong answer to the CAPTCHA
+const WRONG_CAPTCHA_FEEDBACK: &str = "檢測碼輸入錯誤，請確認後重新輸入，謝謝！";
+const SOLD_OUT_FEEDBACK: &str = "去程查無可售車次或選購的車票已售完，請重新輸入訂票條件。";
+
+/// How a session goes
+#[derive(Debug, PartialEq, Clone)]
+pub enum Scenario {
+    /// Everything goes well
+    Normal,
+    /// The answer to the CAPTCHA is rejected no matter what
+    WrongCaptcha,
+    /// No train is available for the search
+    SoldOut,
+    /// Every page responds with 503 Service Unavailable
+    Maintenance,
+    /// The page lacks the elements the scrapers look for
+    Malformed(Page),
+}
+
+/// Pages of the booking flow
+#[derive(Debug, PartialEq, Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Page {
+    Booking,
+    Trains,
+    Passengers,
+    Confirmation,
+}
+
+// Written as `normal`, `wrong-captcha`, `sold-out`, `maintenance` or `malformed:<page>`
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scenario::Normal => write!(f, "normal"),
+            Scenario::WrongCaptcha => write!(f, "wrong-captcha"),
+            Scenario::SoldOut => write!(f, "sold-out"),
+            Scenario::Maintenance => write!(f, "maintenance"),
+            Scenario::Malformed(page) => write!(f, "malformed:{page}"),
+        }
+    }
+}
+
+impl FromStr for Scenario {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "normal" => Ok(Scenario::Normal),
+            "wrong-captcha" => Ok(Scenario::WrongCaptcha),
+            "sold-out" => Ok(Scenario::SoldOut),
+            "maintenance" => Ok(Scenario::Maintenance),
+            s => match s.strip_prefix("malformed:") {
+                Some(page) => page.parse().map(Scenario::Malformed).map_err(|_| format!("unknown page {page}, expecting booking, trains, passengers or confirmation")),
+                None => Err(format!("unknown scenario {s}, expecting normal, wrong-captcha, sold-out, maintenance or malformed:<page>")),
+            },
+        }
+    }
+}
+
+/// A request received by the server
+#[derive(Debug, PartialEq, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    // Path and query, ex. `/IMINT/?locale=tw`
+    pub target: String,
+    // The form-data posted in order
+    pub form: Vec<(String, String)>,
+}
+
+impl RecordedRequest {
+    /// The first value posted for the key
+    pub fn form_value(&self, key: &str) -> Option<&str> {
+        self.form.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    script: VecDeque<Scenario>,
+    captcha_answer: String,
+    sessions: HashMap<String, Session>,
+    requests: Vec<RecordedRequest>,
+}
+
+#[derive(Debug)]
+struct Session {
+    scenario: Scenario,
+    // The booking form posted
+    booking: HashMap<String, String>,
+    outbound: Option<MockTrain>,
+    inbound: Option<MockTrain>,
+}
+
+#[derive(Debug, Clone)]
+struct MockTrain {
+    id: i16,
+    depart: NaiveTime,
+    arrive: NaiveTime,
+    // Ex. `早鳥65折`, `大學生5折`
+    early_bird: Option<&'static str>,
+    student: Option<&'static str>,
+    form_value: String,
+}
+
+/// The server running in background threads until dropped
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// Starts the server on a random local port
+    pub fn start(script: Vec<Scenario>) -> io::Result<Self> {
+        Self::bind("127.0.0.1:0", script, DEFAULT_CAPTCHA_ANSWER)
+    }
+
+    /// Starts the server on the address, accepting the answer to the CAPTCHA (case-insensitive)
+    pub fn bind(addr: impl ToSocketAddrs, script: Vec<Scenario>, captcha_answer: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let server = Self {
+            addr: listener.local_addr()?,
+            state: Arc::new(Mutex::new(State {
+                script: script.into(),
+                captcha_answer: captcha_answer.to_string(),
+                sessions: HashMap::new(),
+                requests: Vec::new(),
+            })),
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+
+        let state = Arc::clone(&server.state);
+        let stopped = Arc::clone(&server.stopped);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                match stream {
+                    Ok(stream) => {
+                        let state = Arc::clone(&state);
+                        thread::spawn(move || {
+                            if let Err(err) = handle_connection(stream, &state) {
+                                warn!("mock server failed to respond: {err}");
+                            }
+                        });
+                    },
+                    Err(err) => warn!("mock server failed to accept: {err}"),
+                }
+            }
+        });
+        Ok(server)
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The requests received so far, oldest first
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        lock(&self.state).requests.clone()
+    }
+
+    /// Blocks the current thread while the server keeps serving
+    pub fn wait(&self) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            thread::park();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake up the accepting thread to let it notice
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+// A panicking connection thread shouldn't take the server down with it
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+struct Request {
+    method: String,
+    target: String,
+    session_id: Option<String>,
+    form: Vec<(String, String)>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+    session_id: Option<String>,
+}
+
+impl Response {
+    fn html(body: String) -> Self {
+        Self { status: "200 OK", content_type: "text/html;charset=UTF-8", body: body.into_bytes(), session_id: None }
+    }
+
+    fn status(status: &'static str) -> Self {
+        Self { status, content_type: "text/html;charset=UTF-8", body: format!("<html><body><h1>{status}</h1></body></html>").into_bytes(), session_id: None }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<State>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let Some(request) = read_request(&mut reader)? else {
+        return Ok(());
+    };
+    debug!("mock server received {} {}", request.method, request.target);
+    lock(state).requests.push(RecordedRequest {
+        method: request.method.clone(),
+        target: request.target.clone(),
+        form: request.form.clone(),
+    });
+
+    let response = respond(&request, &mut lock(state));
+    write_response(stream, &request.method, response)
+}
+
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("malformed request line {request_line:?}")));
+    };
+
+    let mut content_length = 0;
+    let mut session_id = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse().unwrap_or_default(),
+            "cookie" => session_id = value.split(';')
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(name, _)| *name == "JSESSIONID")
+                .map(|(_, value)| value.to_string()),
+            _ => (),
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        session_id,
+        form: form_urlencoded::parse(&body).into_owned().collect(),
+    }))
+}
+
+fn write_response(mut stream: TcpStream, method: &str, response: Response) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nDate: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status, Utc::now().format("%a, %d %b %Y %H:%M:%S GMT"), response.content_type, response.body.len(),
+    );
+    if let Some(session_id) = &response.session_id {
+        head.push_str(&format!("Set-Cookie: JSESSIONID={session_id}; Path=/; HttpOnly\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if method != "HEAD" {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+fn respond(request: &Request, state: &mut State) -> Response {
+    let target = request.target.as_str();
+    match request.method.as_str() {
+        // Telling the time by the `Date` header
+        "HEAD" => Response::status("200 OK"),
+        "GET" if target == "/IMINT/?locale=tw" => start_session(state),
+        "GET" if target.starts_with(CAPTCHA_PATH) => match session(state, request) {
+            Some(Session { scenario: Scenario::Maintenance, .. }) => Response::status("503 Service Unavailable"),
+            Some(_) => captcha_image(),
+            None => Response::status("403 Forbidden"),
+        },
+        "POST" if target.contains("BookingS1Form::IFormSubmitListener") => submit_booking(state, request),
+        "POST" if target.contains("BookingS2Form::IFormSubmitListener") => submit_train_selection(state, request),
+        "POST" if target.contains("BookingS3Form::IFormSubmitListener") => submit_ticket_confirmation(state, request),
+        _ => Response::status("404 Not Found"),
+    }
+}
+
+fn session<'a>(state: &'a mut State, request: &Request) -> Option<&'a mut Session> {
+    // The booking form is posted with the session ID in the URL as well, which must match the cookie
+    let session_id = request.session_id.as_ref()?;
+    if let Some((_, url_session_id)) = request.target.split_once(";jsessionid=") {
+        if !url_session_id.starts_with(session_id.as_str()) {
+            return None;
+        }
+    }
+    state.sessions.get_mut(session_id)
+}
+
+fn start_session(state: &mut State) -> Response {
+    let scenario = state.script.pop_front().unwrap_or(Scenario::Normal);
+    if scenario == Scenario::Maintenance {
+        return Response::status("503 Service Unavailable");
+    }
+    let session_id = format!("{:016X}", RandomState::new().build_hasher().finish());
+    let malformed = scenario == Scenario::Malformed(Page::Booking);
+    state.sessions.insert(session_id.clone(), Session { scenario, booking: HashMap::new(), outbound: None, inbound: None });
+
+    let mut response = Response::html(booking_page(malformed, None));
+    response.session_id = Some(session_id);
+    response
+}
+
+fn submit_booking(state: &mut State, request: &Request) -> Response {
+    let captcha_answer = state.captcha_answer.clone();
+    let Some(session) = session(state, request) else {
+        return Response::status("403 Forbidden");
+    };
+    let form: HashMap<String, String> = request.form.iter().cloned().collect();
+    let value = |key: &str| form.get(key).map(String::as_str).unwrap_or_default();
+
+    let feedback = match &session.scenario {
+        Scenario::Maintenance => return Response::status("503 Service Unavailable"),
+        Scenario::WrongCaptcha => Some(WRONG_CAPTCHA_FEEDBACK),
+        _ if !value("homeCaptcha:securityCode").eq_ignore_ascii_case(&captcha_answer) => Some(WRONG_CAPTCHA_FEEDBACK),
+        _ if value("selectStartStation").is_empty() || value("selectStartStation") == value("selectDestinationStation") => Some("請選擇起訖站"),
+        _ if NaiveDate::parse_from_str(value("toTimeInputField"), "%Y/%m/%d").is_err() => Some("去程日期格式錯誤"),
+        Scenario::SoldOut => Some(SOLD_OUT_FEEDBACK),
+        _ => None,
+    };
+    if let Some(feedback) = feedback {
+        return Response::html(booking_page(false, Some(feedback)));
+    }
+
+    let round_trip = value("tripCon:typesoftrip") == "1";
+    let search_by_train_id = value("bookingMethod") == SEARCH_BY_TRAIN_ID_VALUE;
+    session.booking = form.clone();
+    if search_by_train_id {
+        // The server goes straight to the passenger form
+        let train_id = |key: &str| value(key).parse::<i16>().ok();
+        let Some(outbound_id) = train_id("toTrainIDInputField") else {
+            return Response::html(booking_page(false, Some("請輸入車次號碼")));
+        };
+        session.outbound = Some(train_of_id(outbound_id, 0));
+        if round_trip {
+            let Some(inbound_id) = train_id("backTrainIDInputField") else {
+                return Response::html(booking_page(false, Some("請輸入回程車次號碼")));
+            };
+            session.inbound = Some(train_of_id(inbound_id, 1));
+        }
+        return Response::html(passengers_page(session.scenario == Scenario::Malformed(Page::Passengers)));
+    }
+
+    Response::html(trains_page(session.scenario == Scenario::Malformed(Page::Trains), round_trip))
+}
+
+fn submit_train_selection(state: &mut State, request: &Request) -> Response {
+    let Some(session) = session(state, request) else {
+        return Response::status("403 Forbidden");
+    };
+    let selected = |key: &str, trains: Vec<MockTrain>| {
+        let form_value = request.form.iter().find(|(k, _)| k == key).map(|(_, value)| value)?;
+        trains.into_iter().find(|train| train.form_value == *form_value)
+    };
+    session.outbound = selected("TrainQueryDataViewPanel:TrainGroup", outbound_trains());
+    if session.outbound.is_none() {
+        return Response::html(trains_page(false, false).replace("<!-- feedback -->", &feedback_panel("請選擇去程車次")));
+    }
+    if session.booking.get("tripCon:typesoftrip").map(String::as_str) == Some("1") {
+        session.inbound = selected("TrainQueryDataViewPanel2:TrainGroup", inbound_trains());
+        if session.inbound.is_none() {
+            return Response::html(trains_page(false, true).replace("<!-- feedback -->", &feedback_panel("請選擇回程車次")));
+        }
+    }
+    Response::html(passengers_page(session.scenario == Scenario::Malformed(Page::Passengers)))
+}
+
+fn submit_ticket_confirmation(state: &mut State, request: &Request) -> Response {
+    let Some(session) = session(state, request) else {
+        return Response::status("403 Forbidden");
+    };
+    let form: HashMap<&str, &str> = request.form.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+    let tickets = TicketNums::of(&session.booking);
+    let supplemental_ids = form.keys().filter(|key| key.contains("passengerDataIdNumber")).count();
+
+    let feedback = if session.outbound.is_none() {
+        Some("請選擇車次")
+    } else if form.get("dummyId").is_none_or(|id| id.is_empty()) {
+        Some("請輸入取票識別碼")
+    } else if form.get("TicketMemberSystemInputPanel:TakerMemberSystemDataView:memberSystemRadioGroup") != Some(&MEMBER_RADIO_VALUE) {
+        Some("請選擇會員身分")
+    } else if form.get("agree") != Some(&"on") {
+        Some("請同意訂票須知")
+    } else if supplemental_ids != usize::from(tickets.disabled + tickets.elder) {
+        Some("請輸入旅客身分證字號")
+    } else {
+        None
+    };
+    if let Some(feedback) = feedback {
+        return Response::html(passengers_page(false).replace("<!-- feedback -->", &feedback_panel(feedback)));
+    }
+
+    Response::html(confirmation_page(session, session.scenario == Scenario::Malformed(Page::Confirmation)))
+}
+
+fn feedback_panel(feedback: &str) -> String {
+    format!(r#"<ul class="feedbackPanel"><li class="feedbackPanelERROR"><span class="feedbackPanelERROR">{feedback}</span></li></ul>"#)
+}
+
+fn booking_page(malformed: bool, feedback: Option<&str>) -> String {
+    let feedback = feedback.map(feedback_panel).unwrap_or_default();
+    let time_options: String = ["1201A", "1230A", "500A", "530A", "600A", "630A", "700A", "730A", "800A", "830A", "900A", "930A", "1000A", "1200N", "1230P", "600P", "1130P"].iter()
+        .map(|option| format!(r#"<option value="{option}">{option}</option>"#))
+        .collect();
+    // The CAPTCHA is what goes missing when the page is malformed
+    let captcha = if malformed {
+        String::new()
+    } else {
+        format!(r#"<img id="BookingS1Form_homeCaptcha_passCode" class="captcha-img" src="{CAPTCHA_PATH}&amp;wicket:antiCache=1">"#)
+    };
+    format!(r#"<!DOCTYPE html>
+<html lang="zh-TW"><head><meta charset="UTF-8"><title>台灣高鐵 網路訂票</title></head>
+<body>
+{feedback}
+<form id="BookingS1Form" method="post" action="/IMINT/?wicket:interface=:0:BookingS1Form::IFormSubmitListener">
+  <input type="hidden" name="BookingS1Form:hf:0" value="">
+  <label><input type="radio" name="bookingMethod" value="{SEARCH_BY_TIME_VALUE}" data-target="search-by-time" checked="checked">依時間搜尋車次</label>
+  <label><input type="radio" name="bookingMethod" value="{SEARCH_BY_TRAIN_ID_VALUE}" data-target="search-by-id">直接輸入車次號碼</label>
+  <select name="toTimeTable"><option selected="selected" value="">請選擇</option>{time_options}</select>
+  <input type="text" name="homeCaptcha:securityCode">
+  {captcha}
+</form>
+</body></html>"#)
+}
+
+fn trains_page(malformed: bool, round_trip: bool) -> String {
+    let group = |name: &str, trains: Vec<MockTrain>| -> String {
+        trains.iter().map(|train| {
+            let early_bird = train.early_bird.map(|discount| format!(r#"<p class="early-bird"><span>{discount}</span></p>"#)).unwrap_or_default();
+            let student = train.student.map(|discount| format!(r#"<p class="student"><span>{discount}</span></p>"#)).unwrap_or_default();
+            // The train number is what goes missing when the page is malformed
+            let code = if malformed { String::new() } else { format!(r#"<div class="train-code" id="QueryCode">{}</div>"#, train.id) };
+            let travel_time = train.arrive - train.depart;
+            format!(r#"<label class="result-item">
+  <input type="radio" name="{name}" class="uk-radio" value="{form_value}" QueryCode="{id}" QueryDeparture="{depart}" QueryArrival="{arrive}">
+  <div class="uk-flex">
+    <div id="QueryDeparture">{depart}</div>
+    <div class="duration"><span class="material-icons">schedule</span><span>{hours}:{minutes:02}</span></div>
+    <div id="QueryArrival">{arrive}</div>
+    {code}
+    <div class="discount">{early_bird}{student}</div>
+  </div>
+</label>"#,
+                form_value = train.form_value, id = train.id, depart = train.depart.format("%H:%M"), arrive = train.arrive.format("%H:%M"),
+                hours = travel_time.num_hours(), minutes = travel_time.num_minutes() % 60)
+        }).collect()
+    };
+    let inbound = if round_trip { group("TrainQueryDataViewPanel2:TrainGroup", inbound_trains()) } else { String::new() };
+    format!(r#"<!DOCTYPE html>
+<html lang="zh-TW"><head><meta charset="UTF-8"><title>台灣高鐵 網路訂票</title></head>
+<body>
+<!-- feedback -->
+<form id="BookingS2Form" method="post" action="/IMINT/?wicket:interface=:1:BookingS2Form::IFormSubmitListener">
+  <input type="hidden" name="BookingS2Form:hf:0" value="">
+  <div class="result-listing">{outbound}</div>
+  <div class="result-listing">{inbound}</div>
+</form>
+</body></html>"#, outbound = group("TrainQueryDataViewPanel:TrainGroup", outbound_trains()))
+}
+
+fn passengers_page(malformed: bool) -> String {
+    // The checked member option is what goes missing when the page is malformed
+    let checked = if malformed { "" } else { r#" checked="checked""# };
+    format!(r#"<!DOCTYPE html>
+<html lang="zh-TW"><head><meta charset="UTF-8"><title>台灣高鐵 網路訂票</title></head>
+<body>
+<!-- feedback -->
+<form id="BookingS3FormSP" method="post" action="/IMINT/?wicket:interface=:2:BookingS3Form::IFormSubmitListener">
+  <input type="hidden" name="BookingS3FormSP:hf:0" value="">
+  <input type="text" name="dummyId">
+  <input type="text" name="dummyPhone">
+  <label><input type="radio" name="TicketMemberSystemInputPanel:TakerMemberSystemDataView:memberSystemRadioGroup" value="{MEMBER_RADIO_VALUE}"{checked}>非高鐵會員</label>
+  <label><input type="radio" name="TicketMemberSystemInputPanel:TakerMemberSystemDataView:memberSystemRadioGroup" value="radio46">高鐵會員</label>
+  <input type="checkbox" name="agree" value="on">
+</form>
+</body></html>"#)
+}
+
+fn confirmation_page(session: &Session, malformed: bool) -> String {
+    let value = |key: &str| session.booking.get(key).map(String::as_str).unwrap_or_default();
+    let station = |key: &str| value(key).parse().ok().and_then(Station::from_repr).map_or("", |station| station.chinese_name());
+    let date = |key: &str| NaiveDate::parse_from_str(value(key), "%Y/%m/%d").map(|date| date.format("%m/%d").to_string()).unwrap_or_default();
+    let business = value("trainCon:trainRadioGroup") == "1";
+    let tickets = TicketNums::of(&session.booking);
+
+    let legs = [(&session.outbound, "toTimeInputField", "selectStartStation", "selectDestinationStation"), (&session.inbound, "backTimeInputField", "selectDestinationStation", "selectStartStation")];
+    let mut leg_html = String::new();
+    let mut seats = String::new();
+    let mut total_price = 0;
+    for (leg, (train, date_key, from_key, dest_key)) in legs.iter().enumerate() {
+        let Some(train) = train else {
+            continue;
+        };
+        leg_html.push_str(&format!(r#"<div class="ticket-card">
+  <span class="date"><span>{date}</span></span>
+  <p class="departure-stn"><span>{from}</span></p>
+  <p class="arrival-stn"><span>{dest}</span></p>
+  <span id="setTrainCode{leg}">{id:04}</span>
+  <span id="setTrainDeparture{leg}">{depart}</span>
+  <span id="setTrainArrival{leg}">{arrive}</span>
+</div>"#, date = date(date_key), from = station(from_key), dest = station(dest_key), id = train.id, depart = train.depart.format("%H:%M"), arrive = train.arrive.format("%H:%M")));
+        // Seats next to each other in the same car
+        let car = if business { 6 } else { 5 };
+        for passenger in 0..tickets.total() {
+            seats.push_str(&format!(r#"<div class="seat-label"><span>{car}車{}{}</span></div>"#, 10 + leg, ['A', 'B', 'C', 'D', 'E'][usize::from(passenger) % 5]));
+        }
+        total_price += tickets.price(business);
+    }
+
+    // The PNR is what goes missing when the page is malformed
+    let pnr = if malformed { String::new() } else { r#"<p class="pnr-code"><span>01234567</span><span class="uk-hidden">訂位代號</span></p>"#.to_string() };
+    let class = if business { "商務車廂" } else { "標準車廂" };
+    format!(r#"<!DOCTYPE html>
+<html lang="zh-TW"><head><meta charset="UTF-8"><title>台灣高鐵 網路訂票</title></head>
+<body>
+{pnr}
+{leg_html}
+<div class="info"><p class="info-title">車廂</p><p class="info-data"><span>{class}</span></p></div>
+{seats}
+<p class="total"><span>總票價</span><span id="setTrainTotalPriceValue">TWD {total_price}</span></p>
+</body></html>"#)
+}
+
+struct TicketNums {
+    adult: u8,
+    child: u8,
+    disabled: u8,
+    elder: u8,
+    college: u8,
+}
+
+impl TicketNums {
+    // Ex. `1F`, `0H`
+    fn of(booking: &HashMap<String, String>) -> Self {
+        let num = |row: u8| booking.get(&format!("ticketPanel:rows:{row}:ticketAmount"))
+            .and_then(|amount| amount.get(..amount.len().saturating_sub(1))?.parse().ok())
+            .unwrap_or_default();
+        Self { adult: num(0), child: num(1), disabled: num(2), elder: num(3), college: num(4) }
+    }
+
+    fn total(&self) -> u8 {
+        self.adult + self.child + self.disabled + self.elder + self.college
+    }
+
+    // Of a leg; only the adult tickets are at full price
+    fn price(&self, business: bool) -> u32 {
+        let full_price = if business { 2440 } else { 1490 };
+        u32::from(self.adult) * full_price + u32::from(self.total() - self.adult) * full_price / 2
+    }
+}
+
+fn outbound_trains() -> Vec<MockTrain> {
+    vec![
+        mock_train(803, (7, 30), (9, 5), Some("早鳥65折"), None, "radio18"),
+        mock_train(805, (8, 0), (9, 45), None, None, "radio20"),
+        mock_train(1503, (8, 11), (10, 0), None, Some("大學生5折"), "radio22"),
+        mock_train(609, (8, 46), (10, 20), Some("早鳥8折"), Some("大學生88折"), "radio24"),
+    ]
+}
+
+fn inbound_trains() -> Vec<MockTrain> {
+    vec![
+        mock_train(652, (18, 0), (19, 45), None, None, "radio26"),
+        mock_train(1654, (18, 31), (20, 20), None, Some("大學生5折"), "radio28"),
+    ]
+}
+
+// Trains of any number exist when searching by train ID
+fn train_of_id(id: i16, leg: usize) -> MockTrain {
+    let trains = if leg == 0 { outbound_trains() } else { inbound_trains() };
+    trains.iter().find(|train| train.id == id).cloned()
+        .unwrap_or_else(|| MockTrain { id, form_value: String::new(), ..trains[0].clone() })
+}
+
+fn mock_train(id: i16, depart: (u32, u32), arrive: (u32, u32), early_bird: Option<&'static str>, student: Option<&'static str>, form_value: &str) -> MockTrain {
+    MockTrain {
+        id,
+        depart: NaiveTime::from_hms_opt(depart.0, depart.1, 0).unwrap(),
+        arrive: NaiveTime::from_hms_opt(arrive.0, arrive.1, 0).unwrap(),
+        early_bird,
+        student,
+        form_value: form_value.to_string(),
+    }
+}
+
+fn captcha_image() -> Response {
+    let image = ImageBuffer::from_fn(128, 48, |x, y| if (x / 8 + y / 8) % 2 == 0 { Rgb([240u8, 240, 240]) } else { Rgb([40, 40, 40]) });
+    let mut body = Cursor::new(Vec::new());
+    if let Err(err) = image.write_to(&mut body, ImageFormat::Png) {
+        warn!("failed to encode the CAPTCHA image: {err}");
+        return Response::status("500 Internal Server Error");
+    }
+    Response { status: "200 OK", content_type: "image/png", body: body.into_inner(), session_id: None }
+}
@@ -1,12 +1,17 @@
-use serde::{Deserialize, Serialize};
+use crate::presets::display_fromstr;
+use crate::train_selection::TrainSelectionStrategy;
+use crate::utils::format_travel_time;
+use chrono::{Duration, NaiveDate, NaiveTime};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use serde_with_value_affix::with_affix;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
-use strum::{Display, EnumIter, FromRepr};
+use std::str::FromStr;
+use strum::{Display, EnumIter, EnumString, FromRepr, IntoEnumIterator};
 
-#[derive(Debug, PartialEq, Serialize_repr, Deserialize_repr, EnumIter, FromRepr, Display, Clone)]
+#[derive(Debug, PartialEq, Serialize_repr, EnumIter, FromRepr, Display, Clone)]
 #[repr(u8)]
 pub enum Station {
     Nangang = 1,
@@ -20,17 +25,159 @@ pub enum Station {
     Yunlin,
     Chiayi,
     Tainan,
-    Zuouing,
+    Zuoying,
 }
 
-#[derive(Debug, PartialEq, Serialize_repr, Deserialize_repr)]
+impl Station {
+    pub fn chinese_name(&self) -> &'static str {
+        self.aliases()[0]
+    }
+
+    /// Short code of the station, ex. `TPE`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Station::Nangang => "NAG",
+            Station::Taipei => "TPE",
+            Station::Banqiao => "BAQ",
+            Station::Taoyuan => "TAY",
+            Station::Hsinchu => "HSC",
+            Station::Miaoli => "MIL",
+            Station::Taichung => "TAC",
+            Station::Changhua => "CHH",
+            Station::Yunlin => "YUL",
+            Station::Chiayi => "CHY",
+            Station::Tainan => "TNN",
+            Station::Zuoying => "ZUY",
+        }
+    }
+
+    // Names other than the English one and the code, the Chinese name coming first
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Station::Nangang => &["南港", "nankang"],
+            Station::Taipei => &["台北", "臺北", "taibei"],
+            Station::Banqiao => &["板橋", "banciao", "panchiao"],
+            Station::Taoyuan => &["桃園"],
+            Station::Hsinchu => &["新竹", "xinzhu"],
+            Station::Miaoli => &["苗栗"],
+            Station::Taichung => &["台中", "臺中", "taizhong"],
+            Station::Changhua => &["彰化", "zhanghua"],
+            Station::Yunlin => &["雲林"],
+            Station::Chiayi => &["嘉義", "jiayi"],
+            Station::Tainan => &["台南", "臺南"],
+            // `Zuouing` is how this station was spelled before, kept for the presets saved back then
+            Station::Zuoying => &["左營", "zuouing", "tsoying", "kaohsiung", "高雄"],
+        }
+    }
+
+    fn names(&self) -> impl Iterator<Item = String> + '_ {
+        [self.to_string().to_lowercase(), self.code().to_lowercase()].into_iter()
+            .chain(self.aliases().iter().map(|alias| alias.to_string()))
+    }
+}
+
+/// The station name can't be recognized
+#[derive(Debug, PartialEq)]
+pub struct ParseStationError {
+    pub input: String,
+    // Stations with names close to the input
+    pub suggestions: Vec<Station>,
+}
+
+impl fmt::Display for ParseStationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown station {:?}", self.input)?;
+        let (label, stations) = if self.suggestions.is_empty() {
+            ("expecting one of", Station::iter().collect())
+        } else {
+            ("did you mean", self.suggestions.clone())
+        };
+        let names: Vec<String> = stations.iter().map(|station| format!("{station} ({})", station.chinese_name())).collect();
+        write!(f, ", {label}: {}", names.join(", "))
+    }
+}
+
+impl Error for ParseStationError {}
+
+/// Accepts English names, Chinese names, common romanizations and short codes, case-insensitively
+impl FromStr for Station {
+    type Err = ParseStationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Ignore case, spaces and hyphens, ex. `Pan-chiao`, `Zuo Ying`
+        let normalize = |name: &str| -> String {
+            name.chars()
+                .filter(|c| !c.is_whitespace() && *c != '-')
+                .flat_map(char::to_lowercase)
+                .collect()
+        };
+        let input = normalize(s);
+        let input = input.strip_suffix("站").unwrap_or(&input);
+        if let Some(station) = Station::iter().find(|station| station.names().any(|name| name == input)) {
+            return Ok(station);
+        }
+
+        // Suggest the stations with names within a few typos
+        let max_distance = (input.chars().count() / 3).max(1);
+        let suggestions = Station::iter()
+            .filter(|station| station.names().any(|name| edit_distance(&name, input) <= max_distance || (input.len() >= 3 && name.starts_with(input))))
+            .collect();
+        Err(ParseStationError { input: s.to_string(), suggestions })
+    }
+}
+
+// Levenshtein distance in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut distances: Vec<usize> = (0..=b.len()).collect();
+    for (i, char_a) in a.chars().enumerate() {
+        let mut previous = distances[0];
+        distances[0] = i + 1;
+        for (j, char_b) in b.iter().enumerate() {
+            let substitution = previous + usize::from(char_a != *char_b);
+            previous = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(previous + 1);
+        }
+    }
+    distances[b.len()]
+}
+
+/// Presets before v2 saved the numeric form-data values, while names are accepted as well
+impl<'de> Deserialize<'de> for Station {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Num(u8),
+            Name(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Num(num) => Station::from_repr(num).ok_or_else(|| de::Error::custom(format!("unknown station #{num}"))),
+            Repr::Name(name) => name.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize_repr, Deserialize_repr, EnumIter, FromRepr, Display, EnumString, Default, Clone)]
+#[strum(ascii_case_insensitive)]
 #[repr(u8)]
 pub enum Trip {
+    #[default]
     OneWay = 0,
     RoundTrip,
 }
 
-#[derive(Debug, PartialEq, Serialize_repr, Deserialize_repr, EnumIter, FromRepr, Display, Default, Clone)]
+#[derive(Debug, PartialEq, EnumIter, FromRepr, Display, Default, Clone)]
+#[repr(u8)]
+pub enum BookingMethod {
+    #[default]
+    Time = 0,
+    TrainId,
+}
+
+#[derive(Debug, PartialEq, Serialize_repr, Deserialize_repr, EnumIter, FromRepr, Display, EnumString, Default, Clone)]
+#[strum(ascii_case_insensitive)]
 #[repr(u8)]
 pub enum CabinClass {
     #[default]
@@ -38,9 +185,11 @@ pub enum CabinClass {
     Business,
 }
 
-#[derive(Debug, PartialEq, Serialize_repr, Deserialize_repr, EnumIter, FromRepr, Display, Clone)]
+#[derive(Debug, PartialEq, Serialize_repr, Deserialize_repr, EnumIter, FromRepr, Display, EnumString, Default, Clone)]
+#[strum(ascii_case_insensitive)]
 #[repr(u8)]
 pub enum SeatPref {
+    #[default]
     NoPref = 0,
     Window,
     Aisle,
@@ -52,10 +201,21 @@ pub struct BookingPersisted {
     pub start_station: Station,
     #[serde(rename = "selectDestinationStation")]
     pub dest_station: Station,
+    #[serde(default, rename = "tripCon:typesoftrip")]
+    pub types_of_trip: Trip,
     #[serde(rename = "toTimeInputField")]
     pub outbound_date: String,
     #[serde(rename = "toTimeTable")]
     pub outbound_time: String,
+    #[serde(default, rename = "backTimeInputField", skip_serializing_if = "Option::is_none")]
+    pub inbound_date: Option<String>,
+    #[serde(default, rename = "backTimeTable", skip_serializing_if = "Option::is_none")]
+    pub inbound_time: Option<String>,
+    // Searching by train ID when present, otherwise by time
+    #[serde(default, rename = "toTrainIDInputField", skip_serializing_if = "Option::is_none")]
+    pub to_train_id: Option<i16>,
+    #[serde(default, rename = "backTrainIDInputField", skip_serializing_if = "Option::is_none")]
+    pub back_train_id: Option<i16>,
     #[serde(rename = "seatCon:seatRadioGroup")]
     pub seat_prefer: SeatPref,
     #[serde(default, rename = "trainCon:trainRadioGroup")]
@@ -86,36 +246,91 @@ pub struct Booking {
 
     #[serde(rename = "bookingMethod")]
     pub search_by: String,
-    #[serde(rename = "tripCon:typesoftrip")]
-    pub types_of_trip: Trip,
     #[serde(rename = "homeCaptcha:securityCode")]
     pub security_code: String,
     #[serde(default, rename = "BookingS1Form:hf:0")]
     pub form_mark: String,
-    #[serde(default, rename = "backTimeInputField")]
-    pub inbound_date: Option<String>,
-    #[serde(default, rename = "backTimeTable")]
-    pub inbound_time: Option<String>,
-    #[serde(default, rename = "toTrainIDInputField")]
-    pub to_train_id: Option<i16>,
-    #[serde(default, rename = "backTrainIDInputField")]
-    pub back_train_id: Option<i16>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Clone)]
 pub struct TrainInfo {
     pub id: i16,
-    pub depart: String,
-    pub arrive: String,
-    pub travel_time: String,
-    pub discount_str: String,
+    pub depart: NaiveTime,
+    pub arrive: NaiveTime,
+    // Ex. `1:45`
+    #[serde(serialize_with = "serialize_travel_time")]
+    pub travel_time: Duration,
+    pub discounts: Vec<Discount>,
+    #[serde(skip)]
     pub form_value: String,
 }
 
+fn serialize_travel_time<S: serde::Serializer>(travel_time: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_travel_time(*travel_time))
+}
+
+impl TrainInfo {
+    /// The lowest percentage of the full price among the discounts, or 100 if there isn't any
+    pub fn best_price_percentage(&self) -> u8 {
+        self.discounts.iter().map(Discount::price_percentage).min().unwrap_or(100)
+    }
+
+    // Ex. `早鳥65折, 大學生88折`
+    pub fn discounts_str(&self) -> String {
+        let discounts: Vec<String> = self.discounts.iter().map(Discount::to_string).collect();
+        discounts.join(", ")
+    }
+}
+
+/// Discounts of the train, each with the percentage of the full price (ex. 65 for 65折)
+#[derive(Debug, PartialEq, Serialize, Clone, Copy)]
+#[serde(tag = "type", content = "price_percentage", rename_all = "snake_case")]
+pub enum Discount {
+    EarlyBird(u8),
+    College(u8),
+}
+
+impl Discount {
+    pub fn price_percentage(&self) -> u8 {
+        match self {
+            Discount::EarlyBird(percentage) | Discount::College(percentage) => *percentage,
+        }
+    }
+}
+
+// Shown the same way as the booking site
+impl fmt::Display for Discount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 9折 rather than 90折
+        let price = |percentage: u8| if percentage % 10 == 0 { percentage / 10 } else { percentage };
+        match self {
+            Discount::EarlyBird(percentage) => write!(f, "早鳥{}折", price(*percentage)),
+            Discount::College(percentage) => write!(f, "大學生{}折", price(*percentage)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AvailableTrains {
+    pub outbound: Vec<TrainInfo>,
+    // Only populated for round-trip bookings
+    pub inbound: Vec<TrainInfo>,
+}
+
+#[derive(Debug)]
+pub enum BookingResponse {
+    // The server lists the available trains for us to choose from
+    Trains(AvailableTrains),
+    // The server skips the train list and goes straight to the passenger form (ex. when searching by train ID)
+    TrainSelected(TicketConfirmationFormParams),
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct TrainSelection {
     #[serde(rename = "TrainQueryDataViewPanel:TrainGroup")]
     pub selected_train: String,
+    #[serde(default, rename = "TrainQueryDataViewPanel2:TrainGroup", skip_serializing_if = "Option::is_none")]
+    pub selected_back_train: Option<String>,
     #[serde(default, rename = "BookingS2Form:hf:0")]
     pub form_mark: String,
 }
@@ -165,32 +380,97 @@ fn default_agree() -> String {
     "on".to_string()
 }
 
-// TODO Dynamically disable renaming in Preset serialization
-//  We don't want the field names being renamed when saving as a preset because it actually hurts readability.
-//  Unfortunately serde currently does not support dynamic disabling renaming,
-//  so we are stuck with it for now.
-//  Note: in case it wasn't clear, we can't get rid of the renaming either because we must submit the form using those names.
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+// Since the form-data must be submitted with the field names renamed, serializing a preset as-is hurts readability.
+// This is how presets were saved before v2 and is kept only for migration;
+// the presets file is now saved in the human-readable `presets::HumanPreset` format and converted from/to this struct.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Preset {
+    // Lets the preset be selected by name (ex. `-p commute-south`) instead of its number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     pub booking: BookingPersisted,
     pub ticket_confirmation: TicketConfirmationPersisted,
+    // Picks the train without asking if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub train_selection: Option<TrainSelectionStrategy>,
+    // Same as `train_selection` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_train_selection: Option<TrainSelectionStrategy>,
 }
 
 pub struct BookingFormParams {
     pub session_id: String,
     pub search_by_time_value: String,
+    pub search_by_train_id_value: String,
     pub time_options: Vec<String>,
+    pub captcha_url: String,
 }
 
+#[derive(Debug)]
 pub struct TicketConfirmationFormParams {
     pub member_value: String,
 }
 
+/// The booked tickets shown on the confirmation page
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct BookingResult {
+    pub pnr: String,
+    // In NTD
+    pub total_price: u32,
+    // One leg for one-way tickets, two for round-trip tickets
+    pub legs: Vec<TicketLeg>,
+    #[serde(with = "display_fromstr")]
+    pub class: CabinClass,
+    pub seats: Vec<Seat>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct TicketLeg {
+    pub date: NaiveDate,
+    #[serde(with = "display_fromstr")]
+    pub from: Station,
+    #[serde(with = "display_fromstr")]
+    pub dest: Station,
+    pub depart: NaiveTime,
+    pub arrive: NaiveTime,
+    pub train: i16,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Seat {
+    pub car: u8,
+    // Row and position, ex. `12A`
+    pub number: String,
+}
+
+// Written the same way as the site, ex. 5車12A
+impl fmt::Display for Seat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}車{}", self.car, self.number)
+    }
+}
+
 #[derive(Debug)]
 pub struct ErrorMessages {
     pub errors: Vec<String>,
 }
 
+// Feedback shown by the server when the answer to the CAPTCHA is wrong (ex. "檢測碼輸入錯誤，請確認後重新輸入，謝謝！")
+const WRONG_CAPTCHA_FEEDBACK: &str = "檢測碼";
+
+// Feedback shown by the server when there are no tickets left (ex. "去程查無可售車次或選購的車票已售完，請重新輸入訂票條件。")
+const SOLD_OUT_FEEDBACK: [&str; 2] = ["查無可售車次", "售完"];
+
+impl ErrorMessages {
+    pub fn is_wrong_captcha(&self) -> bool {
+        self.errors.iter().any(|error| error.contains(WRONG_CAPTCHA_FEEDBACK))
+    }
+
+    pub fn is_sold_out(&self) -> bool {
+        self.errors.iter().any(|error| SOLD_OUT_FEEDBACK.iter().any(|feedback| error.contains(feedback)))
+    }
+}
+
 impl fmt::Display for ErrorMessages {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self.errors)
@@ -0,0 +1,274 @@
+//! Parsers of the pages of the booking site, each taking the HTML as it's received.
+//!
+//! They don't talk to the site, so the scrapers can be checked against saved pages.
+//! A page missing what the scrapers look for fails with [`Error::LayoutChanged`] naming the selector,
+//! while the feedback of the site (ex. a wrong answer to the CAPTCHA) fails with [`Error::ServerFeedback`].
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime};
+use scraper::{Element, ElementRef, Html, Selector};
+use crate::configs::Endpoints;
+use crate::error::{Error, Result};
+use crate::models::{AvailableTrains, BookingFormParams, BookingResponse, BookingResult, CabinClass, Discount, ErrorMessages, Seat, Station, TicketConfirmationFormParams, TicketLeg, TrainInfo};
+
+const MEMBER_RADIO_SELECTORS: &str = r#"input[name="TicketMemberSystemInputPanel:TakerMemberSystemDataView:memberSystemRadioGroup"][checked]"#;
+
+/// Parses the booking page (S1) of the session, resolving the CAPTCHA image against the endpoints
+pub fn parse_booking_page(html: &str, session_id: String, endpoints: &Endpoints) -> Result<BookingFormParams> {
+    let document = Html::parse_document(html);
+    let root = document.root_element();
+
+    let search_by_time_value = select_attr(root, r#"input[name="bookingMethod"][data-target="search-by-time"]"#, "value")?;
+    let search_by_train_id_value = select_attr(root, r#"input[name="bookingMethod"][data-target="search-by-id"]"#, "value")?;
+    let time_options_selectors = r#"select[name="toTimeTable"] > option:not([selected])"#;
+    let time_options: Vec<String> = document
+        .select(&Selector::parse(time_options_selectors).unwrap())
+        .filter_map(|elem| elem.value().attr("value").map(|value| value.to_string()))
+        .collect();
+    if time_options.is_empty() {
+        return Err(Error::layout_changed(time_options_selectors));
+    }
+    let captcha_src = select_attr(root, "#BookingS1Form_homeCaptcha_passCode", "src")?;
+
+    Ok(BookingFormParams {
+        session_id,
+        search_by_time_value,
+        search_by_train_id_value,
+        time_options,
+        captcha_url: endpoints.url(&captcha_src),
+    })
+}
+
+/// Parses the response to the booking form: either the train list (S2), or the passenger form (S3) if the server already determined the train
+pub fn parse_trains_page(html: &str) -> Result<BookingResponse> {
+    assert_submission_errors(html)?;
+    let document = Html::parse_document(html);
+
+    // Ex. searching by train ID
+    if let Some(ticket_confirmation_form_params) = parse_ticket_confirmation_form_params(&document)? {
+        return Ok(BookingResponse::TrainSelected(ticket_confirmation_form_params));
+    }
+
+    // The site tells with feedback if there's no train, so an empty list means the trains can't be found
+    let outbound = parse_trains(&document, "TrainQueryDataViewPanel:TrainGroup")?;
+    if outbound.is_empty() {
+        return Err(Error::layout_changed(r#"label input[name="TrainQueryDataViewPanel:TrainGroup"]"#));
+    }
+    Ok(BookingResponse::Trains(AvailableTrains {
+        outbound,
+        inbound: parse_trains(&document, "TrainQueryDataViewPanel2:TrainGroup")?,
+    }))
+}
+
+/// Parses the passenger form (S3) responding to the train selection
+pub fn parse_passengers_page(html: &str) -> Result<TicketConfirmationFormParams> {
+    assert_submission_errors(html)?;
+    parse_ticket_confirmation_form_params(&Html::parse_document(html))?
+        .ok_or_else(|| Error::layout_changed(MEMBER_RADIO_SELECTORS))
+}
+
+/// Parses the booked tickets on the confirmation page, inferring the years of the travel dates from today
+pub fn parse_confirmation_page(html: &str, today: NaiveDate) -> Result<BookingResult> {
+    assert_submission_errors(html)?;
+    parse_booking_result(&Html::parse_document(html), today)
+}
+
+/// Fails with the feedback of the site if the page shows any
+pub fn assert_submission_errors(html: &str) -> std::result::Result<(), ErrorMessages> {
+    let document = Html::parse_document(html);
+    let errors: Vec<String> = document
+        .select(&Selector::parse("span.feedbackPanelERROR").unwrap())
+        .filter_map(|element| element.text().next().map(|text| text.to_string()))
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ErrorMessages{ errors })
+    }
+}
+
+fn parse_discounts(item: ElementRef) -> Result<Vec<Discount>> {
+    let mut discounts = Vec::new();
+    if let Some(discount) = item.select(&Selector::parse("p.early-bird").unwrap()).next() {
+        discounts.push(Discount::EarlyBird(parse_price_percentage(discount).ok_or_else(|| Error::layout_changed("p.early-bird"))?));
+    }
+    if let Some(discount) = item.select(&Selector::parse("p.student").unwrap()).next() {
+        discounts.push(Discount::College(parse_price_percentage(discount).ok_or_else(|| Error::layout_changed("p.student"))?));
+    }
+    Ok(discounts)
+}
+
+// Discounts are shown like `早鳥65折` (65% of the full price) or `5折` (50%)
+fn parse_price_percentage(discount: ElementRef) -> Option<u8> {
+    let text: String = discount.text().collect();
+    let (price, _) = text.split_once('折')?;
+    let digits: String = price.chars().rev().take_while(char::is_ascii_digit).collect::<Vec<char>>().into_iter().rev().collect();
+    match digits.len() {
+        1 => digits.parse::<u8>().ok().map(|price| price * 10),
+        2 => digits.parse().ok(),
+        _ => None,
+    }
+}
+
+// Ex. `06:26`
+fn parse_hour_minute(time: &str, selectors: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| Error::layout_changed(selectors))
+}
+
+// Ex. `1:45`
+fn parse_travel_time(travel_time: &str, selectors: &str) -> Result<Duration> {
+    travel_time.trim().split_once(':')
+        .and_then(|(hours, minutes)| Some(Duration::hours(hours.parse().ok()?) + Duration::minutes(minutes.parse().ok()?)))
+        .ok_or_else(|| Error::layout_changed(selectors))
+}
+
+fn parse_trains(document: &Html, train_group_name: &str) -> Result<Vec<TrainInfo>> {
+    let input_selector = format!(r#"input[name="{train_group_name}"]"#);
+    document
+        .select(&Selector::parse("label").unwrap())
+        .filter_map(|label| {
+            // Labels of the other leg (or unrelated labels) don't carry the radio input of this train group
+            let input = label.select(&Selector::parse(&input_selector).unwrap()).next()?;
+            Some(parse_train(label, input, &input_selector))
+        })
+        .collect()
+}
+
+fn parse_train(label: ElementRef, input: ElementRef, input_selector: &str) -> Result<TrainInfo> {
+    Ok(TrainInfo {
+        id: select_inner_html(label, "#QueryCode")?.trim().parse().map_err(|_| Error::layout_changed("#QueryCode"))?,
+        depart: parse_hour_minute(&select_inner_html(label, "#QueryDeparture")?, "#QueryDeparture")?,
+        arrive: parse_hour_minute(&select_inner_html(label, "#QueryArrival")?, "#QueryArrival")?,
+        travel_time: parse_travel_time(&select_inner_html(label, ".duration > span:nth-of-type(2)")?, ".duration > span:nth-of-type(2)")?,
+        discounts: parse_discounts(label)?,
+        form_value: element_attr(input, "value", input_selector)?,
+    })
+}
+
+fn parse_ticket_confirmation_form_params(document: &Html) -> Result<Option<TicketConfirmationFormParams>> {
+    match document.select(&Selector::parse(MEMBER_RADIO_SELECTORS).unwrap()).next() {
+        Some(elem) => Ok(Some(TicketConfirmationFormParams {
+            member_value: element_attr(elem, "value", MEMBER_RADIO_SELECTORS)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+fn parse_booking_result(document: &Html, today: NaiveDate) -> Result<BookingResult> {
+    let root = document.root_element();
+    let dates: Vec<String> = document.select(&Selector::parse("span.date > span").unwrap()).map(|elem| elem.inner_html()).collect();
+    let departure_stations: Vec<String> = document.select(&Selector::parse("p.departure-stn > span").unwrap()).map(|elem| elem.inner_html()).collect();
+    let arrival_stations: Vec<String> = document.select(&Selector::parse("p.arrival-stn > span").unwrap()).map(|elem| elem.inner_html()).collect();
+    // Round-trip tickets come with a second set of train info indexed by 1
+    let mut legs = Vec::new();
+    for (leg, date) in dates.iter().enumerate() {
+        let train_selectors = format!("#setTrainCode{leg}");
+        let Some(train_code) = document.select(&Selector::parse(&train_selectors).unwrap()).next() else {
+            break;
+        };
+        let departure_selectors = format!("#setTrainDeparture{leg}");
+        let arrival_selectors = format!("#setTrainArrival{leg}");
+        legs.push(TicketLeg {
+            date: parse_ticket_date(date, today).ok_or_else(|| Error::layout_changed("span.date > span"))?,
+            from: parse_station(departure_stations.get(leg), "p.departure-stn > span")?,
+            dest: parse_station(arrival_stations.get(leg), "p.arrival-stn > span")?,
+            depart: parse_hour_minute(&select_inner_html(root, &departure_selectors)?, &departure_selectors)?,
+            arrive: parse_hour_minute(&select_inner_html(root, &arrival_selectors)?, &arrival_selectors)?,
+            train: train_code.inner_html().trim().parse().map_err(|_| Error::layout_changed(&train_selectors))?,
+        });
+    }
+    if legs.is_empty() {
+        return Err(Error::layout_changed("#setTrainCode0"));
+    }
+
+    // The span after the title reading 車廂, which is told apart by its text as selectors can't match on it
+    let class_selectors = "p.info-title + * span";
+    let class = document.select(&Selector::parse("p.info-title").unwrap())
+        .find(|elem| { elem.inner_html().trim() == "車廂" })
+        .and_then(|elem| elem.next_sibling_element())
+        .and_then(|elem| select_first(elem, "span").ok())
+        .and_then(|elem| parse_cabin_class(&elem.inner_html()))
+        .ok_or_else(|| Error::layout_changed(class_selectors))?;
+
+    let price_selectors = "#setTrainTotalPriceValue";
+    Ok(BookingResult {
+        pnr: select_inner_html(root, "p.pnr-code > span:first-child")?.trim().to_string(),
+        total_price: parse_price(&select_inner_html(root, price_selectors)?).ok_or_else(|| Error::layout_changed(price_selectors))?,
+        legs,
+        class,
+        seats: document.select(&Selector::parse("div.seat-label > span").unwrap())
+            .map(|elem| parse_seat(&elem.inner_html()).ok_or_else(|| Error::layout_changed("div.seat-label > span")))
+            .collect::<Result<_>>()?,
+    })
+}
+
+// Ex. `11/01`, or with the year `2025/11/01`; a month and day before today must be in the next year
+fn parse_ticket_date(date: &str, today: NaiveDate) -> Option<NaiveDate> {
+    // Ignore the day of week if any, ex. `11/01 (六)`
+    let date: String = date.trim().chars().take_while(|c| c.is_ascii_digit() || *c == '/').collect();
+    if let Ok(date) = NaiveDate::parse_from_str(&date, "%Y/%m/%d") {
+        return Some(date);
+    }
+    let (month, day) = date.split_once('/')?;
+    let (month, day) = (month.parse().ok()?, day.parse().ok()?);
+    NaiveDate::from_ymd_opt(today.year(), month, day)
+        .filter(|date| *date >= today)
+        .or_else(|| NaiveDate::from_ymd_opt(today.year() + 1, month, day))
+}
+
+fn parse_station(name: Option<&String>, selectors: &str) -> Result<Station> {
+    name.and_then(|name| name.trim().parse().ok()).ok_or_else(|| Error::layout_changed(selectors))
+}
+
+// Ex. `標準車廂`, `商務車廂`
+fn parse_cabin_class(class: &str) -> Option<CabinClass> {
+    if class.contains("商務") {
+        Some(CabinClass::Business)
+    } else if class.contains("標準") {
+        Some(CabinClass::Standard)
+    } else {
+        None
+    }
+}
+
+// Ex. `1,490`, `TWD 1,490`
+fn parse_price(price: &str) -> Option<u32> {
+    let digits: String = price.chars().filter(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+// Ex. `5車12A`
+fn parse_seat(seat: &str) -> Option<Seat> {
+    let (car, number) = seat.trim().split_once('車')?;
+    let number = number.trim();
+    if number.is_empty() {
+        return None;
+    }
+    Some(Seat {
+        car: car.trim().parse().ok()?,
+        number: number.to_string(),
+    })
+}
+
+/// Finds the first element matching the selectors, or reports the page layout has changed
+fn select_first<'a>(element: ElementRef<'a>, selectors: &str) -> Result<ElementRef<'a>> {
+    element
+        .select(&Selector::parse(selectors).unwrap())
+        .next()
+        .ok_or_else(|| Error::layout_changed(selectors))
+}
+
+fn select_inner_html(element: ElementRef, selectors: &str) -> Result<String> {
+    Ok(select_first(element, selectors)?.inner_html())
+}
+
+fn select_attr(element: ElementRef, selectors: &str, attr: &str) -> Result<String> {
+    element_attr(select_first(element, selectors)?, attr, selectors)
+}
+
+fn element_attr(element: ElementRef, attr: &str, selectors: &str) -> Result<String> {
+    element
+        .value()
+        .attr(attr)
+        .map(|value| value.to_string())
+        .ok_or_else(|| Error::layout_changed(&format!("{selectors}[{attr}]")))
+}
@@ -0,0 +1,393 @@
+use crate::dates::DateExpr;
+use crate::error::{Error, Result};
+use crate::train_selection::TrainSelectionStrategy;
+use crate::models::{BookingPersisted, CabinClass, Preset, SeatPref, Station, TicketConfirmationPersisted, Trip};
+use crate::utils::{format_supplement_ids_key, parse_time_option, to_time_option};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+pub const PRESETS_VERSION: u32 = 2;
+// How long to wait for another process to finish modifying the presets
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+// A lock older than this is left behind by a crashed process
+const LOCK_STALE_AFTER: Duration = Duration::from_secs(60);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The presets file, saved in the human-readable format
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PresetsFile {
+    pub version: u32,
+    pub presets: Vec<HumanPreset>,
+}
+
+/// The human-readable counterpart of [`Preset`]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct HumanPreset {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(with = "display_fromstr")]
+    pub from: Station,
+    #[serde(with = "display_fromstr")]
+    pub to: Station,
+    #[serde(default, with = "display_fromstr")]
+    pub trip: Trip,
+    pub date: String,
+    // Either `HH:MM` or the time table option of the booking form (ex. `930A`)
+    pub time: String,
+    // Book this train instead of searching by time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub train: Option<i16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_train: Option<i16>,
+    // How to pick the train without asking, ex. `fastest`, `prefer:803,805`, `arrive-by:10:30`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub train_selection: Option<TrainSelectionStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_train_selection: Option<TrainSelectionStrategy>,
+    #[serde(default, with = "display_fromstr")]
+    pub class: CabinClass,
+    #[serde(default, with = "display_fromstr")]
+    pub seat: SeatPref,
+    pub tickets: TicketCounts,
+    pub personal_id: String,
+    #[serde(default)]
+    pub phone: String,
+    // Passengers who must provide their own personal IDs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passengers: Vec<Passenger>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct TicketCounts {
+    #[serde(default)]
+    pub adult: u8,
+    #[serde(default)]
+    pub child: u8,
+    #[serde(default)]
+    pub disabled: u8,
+    #[serde(default)]
+    pub elder: u8,
+    #[serde(default)]
+    pub college: u8,
+}
+
+impl From<&BookingPersisted> for TicketCounts {
+    fn from(booking: &BookingPersisted) -> Self {
+        Self {
+            adult: booking.adult_ticket_num,
+            child: booking.child_ticket_num,
+            disabled: booking.disabled_ticket_num,
+            elder: booking.elder_ticket_num,
+            college: booking.college_ticket_num,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Passenger {
+    #[serde(rename = "type")]
+    pub passenger_type: PassengerType,
+    pub id: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PassengerType {
+    Disabled,
+    Elder,
+}
+
+impl From<&Preset> for HumanPreset {
+    fn from(preset: &Preset) -> Self {
+        let booking = &preset.booking;
+        let supplemental_ids = &preset.ticket_confirmation.supplemental_ids;
+
+        // Supplemental IDs are ordinal-oriented: disabled passengers come after adults and children, then elders
+        let mut passengers = Vec::new();
+        let mut cursor = booking.adult_ticket_num + booking.child_ticket_num;
+        for (passenger_type, num) in [(PassengerType::Disabled, booking.disabled_ticket_num), (PassengerType::Elder, booking.elder_ticket_num)] {
+            for i in 0..num {
+                passengers.push(Passenger {
+                    passenger_type,
+                    id: supplemental_ids.get(&format_supplement_ids_key(cursor + i)).cloned().unwrap_or_default(),
+                });
+            }
+            cursor += num;
+        }
+
+        Self {
+            name: preset.name.clone(),
+            from: booking.start_station.clone(),
+            to: booking.dest_station.clone(),
+            trip: booking.types_of_trip.clone(),
+            date: booking.outbound_date.clone(),
+            time: humanize_time_option(&booking.outbound_time),
+            train: booking.to_train_id,
+            return_date: booking.inbound_date.clone(),
+            return_time: booking.inbound_time.as_deref().map(humanize_time_option),
+            return_train: booking.back_train_id,
+            train_selection: preset.train_selection.clone(),
+            return_train_selection: preset.return_train_selection.clone(),
+            class: booking.class_type.clone(),
+            seat: booking.seat_prefer.clone(),
+            tickets: TicketCounts::from(booking),
+            personal_id: preset.ticket_confirmation.personal_id.clone(),
+            phone: preset.ticket_confirmation.phone_num.clone(),
+            passengers,
+        }
+    }
+}
+
+impl TryFrom<&HumanPreset> for Preset {
+    type Error = Error;
+
+    fn try_from(preset: &HumanPreset) -> Result<Self> {
+        let tickets = &preset.tickets;
+        let mut supplemental_ids = HashMap::new();
+        let mut cursor = tickets.adult + tickets.child;
+        for (passenger_type, num) in [(PassengerType::Disabled, tickets.disabled), (PassengerType::Elder, tickets.elder)] {
+            let ids: Vec<&String> = preset.passengers.iter()
+                .filter(|passenger| passenger.passenger_type == passenger_type)
+                .map(|passenger| &passenger.id)
+                .collect();
+            if ids.len() != num as usize {
+                return Err(Error::Preset(format!("{num} {passenger_type:?} tickets but {} {passenger_type:?} passengers", ids.len())));
+            }
+            for (i, id) in ids.into_iter().enumerate() {
+                supplemental_ids.insert(format_supplement_ids_key(cursor + i as u8), id.clone());
+            }
+            cursor += num;
+        }
+
+        // Catch malformed date expressions early rather than in the middle of booking
+        for date in std::iter::once(&preset.date).chain(&preset.return_date) {
+            DateExpr::parse(date).map_err(|err| Error::Preset(err.to_string()))?;
+        }
+
+        Ok(Preset {
+            name: preset.name.clone(),
+            booking: BookingPersisted {
+                start_station: preset.from.clone(),
+                dest_station: preset.to.clone(),
+                types_of_trip: preset.trip.clone(),
+                outbound_date: preset.date.clone(),
+                outbound_time: to_time_option(&preset.time).map_err(|err| Error::Preset(err.to_string()))?,
+                inbound_date: preset.return_date.clone(),
+                inbound_time: preset.return_time.as_deref().map(to_time_option).transpose().map_err(|err| Error::Preset(err.to_string()))?,
+                to_train_id: preset.train,
+                back_train_id: preset.return_train,
+                seat_prefer: preset.seat.clone(),
+                class_type: preset.class.clone(),
+                adult_ticket_num: tickets.adult,
+                child_ticket_num: tickets.child,
+                disabled_ticket_num: tickets.disabled,
+                elder_ticket_num: tickets.elder,
+                college_ticket_num: tickets.college,
+            },
+            ticket_confirmation: TicketConfirmationPersisted {
+                personal_id: preset.personal_id.clone(),
+                phone_num: preset.phone.clone(),
+                supplemental_ids,
+            },
+            train_selection: preset.train_selection.clone(),
+            return_train_selection: preset.return_train_selection.clone(),
+        })
+    }
+}
+
+fn humanize_time_option(option: &str) -> String {
+    match parse_time_option(option) {
+        Some(time) => time.format("%H:%M").to_string(),
+        None => option.to_string(),
+    }
+}
+
+
+/// Loads the presets file in either format, or returns `None` if the file doesn't exist
+pub fn load_presets(path: &str) -> Result<Option<Vec<Preset>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let value: Value = serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| Error::Preset(format!("failed to parse {path}: {err}")))?;
+    Ok(Some(parse_presets(value).map_err(|err| Error::Preset(format!("failed to parse {path}: {err}")))?))
+}
+
+fn parse_presets(value: Value) -> std::result::Result<Vec<Preset>, String> {
+    match value {
+        // Presets before v2 were saved as a plain array of the form-data
+        Value::Array(_) => serde_json::from_value(value).map_err(|err| err.to_string()),
+        _ => {
+            let presets_file: PresetsFile = serde_json::from_value(value).map_err(|err| err.to_string())?;
+            if presets_file.version != PRESETS_VERSION {
+                return Err(format!("unsupported version {}", presets_file.version));
+            }
+            presets_file.presets.iter()
+                .map(|preset| Preset::try_from(preset).map_err(|err| match err {
+                    Error::Preset(message) => message,
+                    err => err.to_string(),
+                }))
+                .collect()
+        },
+    }
+}
+
+/// Finds the preset by either its number (starting from 1) or its name, and returns its index along with it
+pub fn find_preset<'a>(presets: &'a [Preset], key: &str) -> Result<(usize, &'a Preset)> {
+    let idx = match key.parse::<usize>() {
+        Ok(preset_num) => preset_num.checked_sub(1).filter(|&idx| idx < presets.len()),
+        Err(_) => presets.iter().position(|preset| preset.name.as_deref() == Some(key)),
+    };
+    idx.map(|idx| (idx, &presets[idx]))
+        .ok_or_else(|| Error::Preset(format!("preset {key} not found")))
+}
+
+/// Checks whether the name can be given to a new preset, `excluded` being the index of the preset to be renamed if any
+pub fn validate_preset_name(presets: &[Preset], name: &str, excluded: Option<usize>) -> Result<()> {
+    if name.is_empty() || name.parse::<usize>().is_ok() {
+        // Numbers are reserved for selecting presets by their positions
+        return Err(Error::Preset(format!("invalid preset name {name:?}, it must not be empty or a number")));
+    }
+    let taken = presets.iter().enumerate()
+        .any(|(idx, preset)| Some(idx) != excluded && preset.name.as_deref() == Some(name));
+    if taken {
+        return Err(Error::Preset(format!("preset name {name} is already taken")));
+    }
+    Ok(())
+}
+
+/// Saves the presets in the human-readable format
+pub fn save_presets(path: &str, presets: &[Preset]) -> Result<()> {
+    let presets_file = PresetsFile {
+        version: PRESETS_VERSION,
+        presets: presets.iter().map(HumanPreset::from).collect(),
+    };
+
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Write to a temporary file first so the presets won't be lost if anything goes wrong halfway
+    let tmp_path = format!("{path}.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer_pretty(&mut writer, &presets_file).map_err(io::Error::other)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// Exclusive access to the presets file, held as `<path>.lock` until dropped
+struct PresetsLock {
+    path: PathBuf,
+}
+
+impl PresetsLock {
+    fn acquire(presets_path: &str) -> Result<Self> {
+        let path = PathBuf::from(format!("{presets_path}.lock"));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let started_at = SystemTime::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self { path }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    let is_stale = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .map(|modified| modified.elapsed().unwrap_or_default() > LOCK_STALE_AFTER)
+                        .unwrap_or(false);
+                    if is_stale {
+                        warn!("removing stale lock {}", path.display());
+                        let _ = fs::remove_file(&path);
+                    } else if started_at.elapsed().unwrap_or_default() > LOCK_TIMEOUT {
+                        return Err(Error::Preset(format!("{presets_path} is being modified by another process, remove {} if not", path.display())));
+                    } else {
+                        sleep(LOCK_RETRY_INTERVAL);
+                    }
+                },
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for PresetsLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Loads the latest presets, modifies and saves them while holding the lock, so modifications made by other processes in the meantime won't be lost
+pub fn update_presets<T>(path: &str, modify: impl FnOnce(&mut Vec<Preset>) -> Result<T>) -> Result<T> {
+    let _lock = PresetsLock::acquire(path)?;
+    let mut presets = load_presets(path)?.unwrap_or_default();
+    let output = modify(&mut presets)?;
+    save_presets(path, &presets)?;
+    Ok(output)
+}
+
+/// Appends the preset to the presets file and returns its number
+pub fn append_preset(path: &str, preset: Preset) -> Result<usize> {
+    update_presets(path, |presets| {
+        if let Some(name) = &preset.name {
+            validate_preset_name(presets, name, None)?;
+        }
+        presets.push(preset);
+        Ok(presets.len())
+    })
+}
+
+/// Upgrades the presets file to the latest format and returns the number of presets migrated, or `None` if it's already up-to-date.
+/// The original file is kept as `<path>.v1.bak`.
+pub fn migrate_presets(path: &str) -> Result<Option<usize>> {
+    let _lock = PresetsLock::acquire(path)?;
+    let content = fs::read_to_string(path)?;
+    let value: Value = serde_json::from_str(&content)
+        .map_err(|err| Error::Preset(format!("failed to parse {path}: {err}")))?;
+    if !value.is_array() {
+        // Already up-to-date
+        return Ok(None);
+    }
+
+    let presets = parse_presets(value).map_err(|err| Error::Preset(format!("failed to parse {path}: {err}")))?;
+    fs::write(format!("{path}.v1.bak"), content)?;
+    save_presets(path, &presets)?;
+
+    Ok(Some(presets.len()))
+}
+
+/// Serializes enums by their names instead of the form-data values
+pub(crate) mod display_fromstr {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(|err| de::Error::custom(format!("{value}: {err}")))
+    }
+}
@@ -0,0 +1,164 @@
+use crate::booking_args::BookingArgs;
+use crate::cli::{ask_for_booking_worksheet, ask_for_ticket_confirmation_worksheet, print_preset, print_presets};
+use chrono_tz::Tz::Asia__Taipei;
+use clap::Subcommand;
+use thsr_ticket_rs::models::{Preset, Trip};
+use thsr_ticket_rs::presets::{append_preset, find_preset, load_presets, migrate_presets, update_presets, validate_preset_name};
+use thsr_ticket_rs::prompter::Prompter;
+use thsr_ticket_rs::utils::default_time_options;
+use thsr_ticket_rs::Result;
+
+#[derive(Subcommand, Debug)]
+pub enum PresetsCommand {
+    /// List the presets in brief
+    List,
+    /// Show the details of a preset
+    Show {
+        /// Number or name of the preset
+        preset: String,
+    },
+    /// Add a new preset by answering the same questions as booking
+    Add {
+        /// Name to select the preset by
+        #[arg(long)]
+        name: Option<String>,
+
+        #[command(flatten)]
+        booking: BookingArgs,
+    },
+    /// Edit a preset, keeping the current values by default; or only change the values given as options
+    Edit {
+        /// Number or name of the preset
+        preset: String,
+
+        #[command(flatten)]
+        booking: BookingArgs,
+    },
+    /// Remove a preset
+    Remove {
+        /// Number or name of the preset
+        preset: String,
+    },
+    /// Give a preset a new name
+    Rename {
+        /// Number or name of the preset
+        preset: String,
+        new_name: String,
+    },
+    /// Upgrade the presets file to the human-readable format; the original file is kept as `<file>.v1.bak`
+    Migrate,
+}
+
+pub fn run_presets_command(presets_file: &str, command: PresetsCommand, prompter: &mut dyn Prompter) -> Result<()> {
+    let presets = || -> Result<Vec<Preset>> { Ok(load_presets(presets_file)?.unwrap_or_default()) };
+
+    match command {
+        PresetsCommand::List => {
+            let presets = presets()?;
+            if presets.is_empty() {
+                println!("No presets in {presets_file}");
+            }
+            presets.iter().enumerate().for_each(|(idx, preset)| println!("{}", summarize_preset(idx + 1, preset)));
+        },
+        PresetsCommand::Show { preset } => {
+            let presets = presets()?;
+            let (idx, preset) = find_preset(&presets, &preset)?;
+            print_preset(idx + 1, preset, &Asia__Taipei);
+        },
+        PresetsCommand::Add { name, booking: given } => {
+            if let Some(name) = &name {
+                // Check before asking anything, it's checked again when saving
+                validate_preset_name(&presets()?, name, None)?;
+            }
+            let booking = ask_for_booking_worksheet(prompter, &default_time_options(), &Asia__Taipei, None, &given)?;
+            let ticket_confirmation = ask_for_ticket_confirmation_worksheet(prompter, &booking, None, &given)?;
+            let preset = Preset {
+                name,
+                booking,
+                ticket_confirmation,
+                train_selection: given.train_selection.clone(),
+                return_train_selection: given.return_train_selection.clone(),
+            };
+            let preset_num = append_preset(presets_file, preset.clone())?;
+            println!("Added:");
+            print_preset(preset_num, &preset, &Asia__Taipei);
+        },
+        PresetsCommand::Edit { preset: key, booking: given } => {
+            let (booking, ticket_confirmation, train_selection, return_train_selection) = {
+                let presets = presets()?;
+                let (_, preset) = find_preset(&presets, &key)?;
+                if given.is_empty() {
+                    let booking = ask_for_booking_worksheet(prompter, &default_time_options(), &Asia__Taipei, Some(&preset.booking), &given)?;
+                    let ticket_confirmation = ask_for_ticket_confirmation_worksheet(prompter, &booking, Some(&preset.ticket_confirmation), &given)?;
+                    (booking, ticket_confirmation, preset.train_selection.clone(), preset.return_train_selection.clone())
+                } else {
+                    // Only change what's given without asking
+                    let preset = given.apply_to_preset(preset)?;
+                    (preset.booking, preset.ticket_confirmation, preset.train_selection, preset.return_train_selection)
+                }
+            };
+            let (idx, preset) = update_presets(presets_file, |presets| {
+                // Look it up again in case the presets were modified while answering the questions
+                let (idx, _) = find_preset(presets, &key)?;
+                presets[idx].booking = booking;
+                presets[idx].ticket_confirmation = ticket_confirmation;
+                presets[idx].train_selection = train_selection;
+                presets[idx].return_train_selection = return_train_selection;
+                Ok((idx, presets[idx].clone()))
+            })?;
+            println!("Updated:");
+            print_preset(idx + 1, &preset, &Asia__Taipei);
+        },
+        PresetsCommand::Remove { preset: key } => {
+            let (idx, removed, presets) = update_presets(presets_file, |presets| {
+                let (idx, _) = find_preset(presets, &key)?;
+                Ok((idx, presets.remove(idx), presets.clone()))
+            })?;
+            println!("Removed {}", summarize_preset(idx + 1, &removed));
+            if idx < presets.len() {
+                println!("The presets after it are renumbered:");
+                print_presets(&presets, &Asia__Taipei);
+            }
+        },
+        PresetsCommand::Rename { preset: key, new_name } => {
+            let (idx, preset) = update_presets(presets_file, |presets| {
+                let (idx, _) = find_preset(presets, &key)?;
+                validate_preset_name(presets, &new_name, Some(idx))?;
+                presets[idx].name = Some(new_name);
+                Ok((idx, presets[idx].clone()))
+            })?;
+            println!("Renamed {}", summarize_preset(idx + 1, &preset));
+        },
+        PresetsCommand::Migrate => {
+            match migrate_presets(presets_file)? {
+                Some(num) => println!("Migrated {num} preset(s) in {presets_file}, the original file is kept as {presets_file}.v1.bak"),
+                None => println!("{presets_file} is already up-to-date"),
+            }
+        },
+    }
+
+    Ok(())
+}
+
+// Ex. `#1 commute-south: Taipei -> Zuoying, 2024/01/01 930A`
+fn summarize_preset(preset_num: usize, preset: &Preset) -> String {
+    let booking = &preset.booking;
+    let name = preset.name.as_deref().map(|name| format!(" {name}")).unwrap_or_default();
+    let when = |date: &str, time: &str, train_id: Option<i16>| match train_id {
+        Some(train_id) => format!("{date} train {train_id}"),
+        None => format!("{date} {time}"),
+    };
+    let mut summary = format!(
+        "#{preset_num}{name}: {:?} -> {:?}, {}",
+        booking.start_station,
+        booking.dest_station,
+        when(&booking.outbound_date, &booking.outbound_time, booking.to_train_id),
+    );
+    if booking.types_of_trip == Trip::RoundTrip {
+        summary.push_str(&format!(
+            ", return {}",
+            when(booking.inbound_date.as_deref().unwrap_or_default(), booking.inbound_time.as_deref().unwrap_or_default(), booking.back_train_id),
+        ));
+    }
+    summary
+}
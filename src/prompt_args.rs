@@ -0,0 +1,26 @@
+use clap::Args;
+use thsr_ticket_rs::prompter::{Prompter, ScriptedPrompter, TerminalPrompter, UnattendedPrompter};
+use thsr_ticket_rs::Result;
+
+/// Where the answers to the questions come from, shared by the commands asking any
+#[derive(Args, Debug, Default, Clone)]
+pub struct PromptArgs {
+    /// Answer the questions from this file in order instead of the terminal, one answer per line (an empty line takes the default)
+    #[arg(long, global = true)]
+    pub answers: Option<String>,
+
+    /// Fail instead of asking anything, for runs with everything given by the preset and the options
+    #[arg(long, global = true, conflicts_with = "answers")]
+    pub unattended: bool,
+}
+
+impl PromptArgs {
+    /// Builds the prompter, asking on stderr instead of stdout if `to_stderr`
+    pub fn build_prompter(&self, to_stderr: bool) -> Result<Box<dyn Prompter>> {
+        Ok(match &self.answers {
+            Some(path) => Box::new(ScriptedPrompter::from_file(path)?),
+            None if self.unattended => Box::new(UnattendedPrompter),
+            None => Box::new(TerminalPrompter::new(to_stderr)),
+        })
+    }
+}
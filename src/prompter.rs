@@ -0,0 +1,98 @@
+use crate::error::{Error, Result};
+use log::debug;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, stdin, IsTerminal, Write};
+
+/// Answers the questions asked along the booking flow
+pub trait Prompter: Debug {
+    /// Asks the question and returns the answer trimmed, where an empty answer takes the default if any
+    fn ask(&mut self, question: &str) -> Result<String>;
+
+    /// Whether someone is there to answer, who may be asked for more than the booking needs (ex. saving the answers as a preset)
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+/// Asks on the terminal and reads the answer from stdin
+#[derive(Debug)]
+pub struct TerminalPrompter {
+    // Keeps stdout clean for the machine-readable output
+    to_stderr: bool,
+}
+
+impl TerminalPrompter {
+    pub fn new(to_stderr: bool) -> Self {
+        Self { to_stderr }
+    }
+}
+
+impl Prompter for TerminalPrompter {
+    fn ask(&mut self, question: &str) -> Result<String> {
+        if self.to_stderr {
+            eprintln!("{question}");
+        } else {
+            println!("{question}");
+            io::stdout().flush()?;
+        }
+        match stdin().lines().next() {
+            Some(line) => Ok(line?.trim().to_string()),
+            None => Err(Error::InvalidInput(format!("unexpected end of input, asked: {question}"))),
+        }
+    }
+
+    fn is_interactive(&self) -> bool {
+        stdin().is_terminal()
+    }
+}
+
+/// Answers the questions in order from a script, ex. for automation and tests
+#[derive(Debug)]
+pub struct ScriptedPrompter {
+    answers: VecDeque<String>,
+    // Where the answers come from, for the errors
+    source: String,
+    // How many answers are taken so far
+    taken: usize,
+}
+
+impl ScriptedPrompter {
+    pub fn new<S: Into<String>>(answers: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            answers: answers.into_iter().map(Into::into).collect(),
+            source: "the script".to_string(),
+            taken: 0,
+        }
+    }
+
+    /// Loads the answers file, one answer per line; empty lines take the defaults and lines starting with `#` are comments
+    pub fn from_file(path: &str) -> Result<Self> {
+        let script = fs::read_to_string(path)
+            .map_err(|err| Error::InvalidInput(format!("failed to read the answers file {path}: {err}")))?;
+        let answers = script.lines().filter(|line| !line.trim_start().starts_with('#'));
+        Ok(Self { source: path.to_string(), ..Self::new(answers) })
+    }
+}
+
+impl Prompter for ScriptedPrompter {
+    fn ask(&mut self, question: &str) -> Result<String> {
+        let answer = self.answers.pop_front()
+            .ok_or_else(|| Error::InvalidInput(format!("no answer left in {} for: {question}", self.source)))?;
+        self.taken += 1;
+        // Leave a trace to tell which answer went to which question when the script goes out of step, without the answer itself (ex. personal IDs)
+        debug!("answer #{} of {} to: {question}", self.taken, self.source);
+        Ok(answer.trim().to_string())
+    }
+}
+
+/// Fails whenever asked, for unattended runs that must have everything given up front
+#[derive(Debug)]
+pub struct UnattendedPrompter;
+
+impl Prompter for UnattendedPrompter {
+    fn ask(&mut self, question: &str) -> Result<String> {
+        Err(Error::InvalidInput(format!("asked while unattended: {question}")))
+    }
+}
@@ -0,0 +1,182 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::{Mutex, OnceLock};
+use strum::{Display, EnumString};
+use thsr_ticket_rs::history::HistoryEntry;
+use thsr_ticket_rs::models::{BookingResult, TrainInfo};
+use thsr_ticket_rs::utils::format_date;
+
+/// Prints the human-readable messages, which go to stderr instead when stdout is reserved for the machine-readable output
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::render::output_format() == $crate::render::OutputFormat::Text {
+            println!($($arg)*)
+        } else {
+            eprintln!($($arg)*)
+        }
+    };
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum OutputFormat {
+    // Tables and messages for humans
+    #[default]
+    Text,
+    // A single JSON document with the trains, the selection and the result, printed at the end
+    Json,
+    // A JSON object per line for each step as it happens
+    Jsonl,
+}
+
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+// What the `json` output collects until the end
+static DOCUMENT: Mutex<Option<Value>> = Mutex::new(None);
+
+/// Sets the output format for the rest of the run; only the first call takes effect
+pub fn set_output_format(format: OutputFormat) {
+    let _ = OUTPUT_FORMAT.set(format);
+}
+
+pub fn output_format() -> OutputFormat {
+    OUTPUT_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Steps of the booking flow, reported as they happen
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    SessionStarted,
+    CaptchaFetched,
+    CaptchaRejected {
+        attempt: u32,
+    },
+    TrainsFound {
+        outbound: &'a [TrainInfo],
+        inbound: &'a [TrainInfo],
+    },
+    TrainSelected {
+        // `departure` or `return`
+        leg: &'a str,
+        train: &'a TrainInfo,
+        // Absent if the user picked the train
+        #[serde(skip_serializing_if = "Option::is_none")]
+        strategy: Option<String>,
+    },
+    Submitted,
+    Booked {
+        result: &'a BookingResult,
+    },
+    // Watch mode
+    Searching {
+        poll: u32,
+    },
+    Matched {
+        message: &'a str,
+    },
+    // Scheduled booking
+    Scheduled {
+        submit_at: String,
+    },
+    Failed {
+        message: String,
+        exit_code: u8,
+    },
+}
+
+/// Reports the event in the output format
+pub fn emit(event: Event) {
+    match output_format() {
+        OutputFormat::Text => {
+            // The other events are told by the messages along the way
+            if let Event::Booked { result } = event {
+                println!("{}", render_booking_result(result));
+            }
+        },
+        OutputFormat::Json => {
+            let mut document = DOCUMENT.lock().unwrap_or_else(|err| err.into_inner());
+            let document = document.get_or_insert_with(|| json!({}));
+            match event {
+                Event::TrainsFound { outbound, inbound } => document["trains"] = json!({ "outbound": outbound, "inbound": inbound }),
+                Event::TrainSelected { leg, train, strategy } => document["selection"][leg] = json!({ "train": train, "strategy": strategy }),
+                Event::Booked { result } => document["result"] = json!(result),
+                Event::Failed { message, exit_code } => document["error"] = json!({ "message": message, "exit_code": exit_code }),
+                _ => (),
+            }
+        },
+        OutputFormat::Jsonl => match serde_json::to_string(&event) {
+            Ok(line) => println!("{line}"),
+            Err(err) => eprintln!("failed to serialize {event:?}: {err}"),
+        },
+    }
+}
+
+/// Prints the document collected for the `json` output, if any
+pub fn finish() {
+    if output_format() != OutputFormat::Json {
+        return;
+    }
+    let document = DOCUMENT.lock().unwrap_or_else(|err| err.into_inner()).take();
+    if let Some(document) = document {
+        println!("{document:#}");
+    }
+}
+
+/// The booked tickets as a table
+pub fn render_booking_result(booking_result: &BookingResult) -> String {
+    let mut lines = vec![
+        String::new(),
+        String::new(),
+        "----------- Booking Results -----------".to_string(),
+        format!("Ticket ID: {}", booking_result.pnr),
+        format!("Total price: {}", format_price(booking_result.total_price)),
+        "---------------------------------------".to_string(),
+        "Date        From      Dest      Depart  Arrive  Train".to_string(),
+    ];
+    for leg in &booking_result.legs {
+        lines.push(format!(
+            "{:<12}{:<10}{:<10}{:<8}{:<8}{}",
+            format_date(leg.date), leg.from.to_string(), leg.dest.to_string(), leg.depart.format("%H:%M").to_string(), leg.arrive.format("%H:%M").to_string(), leg.train,
+        ));
+    }
+    for seat in &booking_result.seats {
+        lines.push(format!("{} {seat}", booking_result.class));
+    }
+    lines.join("\n")
+}
+
+/// A past booking in brief
+pub fn render_history_entry(entry: &HistoryEntry) -> String {
+    let result = &entry.result;
+    let mut lines = vec![format!("{} {} -> {}, PNR {}, {}", format_date(entry.date), entry.from, entry.to, result.pnr, format_price(result.total_price))];
+    for leg in &result.legs {
+        lines.push(format!("  {} {} -> {} train {} ({} -> {})", format_date(leg.date), leg.from, leg.dest, leg.train, leg.depart.format("%H:%M"), leg.arrive.format("%H:%M")));
+    }
+    let seats: Vec<String> = result.seats.iter().map(ToString::to_string).collect();
+    lines.push(format!("  {} {}", result.class, seats.join(", ")));
+    let tickets = &entry.tickets;
+    let tickets: Vec<String> = [("adult", tickets.adult), ("child", tickets.child), ("disabled", tickets.disabled), ("elder", tickets.elder), ("college", tickets.college)]
+        .iter()
+        .filter(|(_, num)| *num > 0)
+        .map(|(passenger_type, num)| format!("{num} {passenger_type}"))
+        .collect();
+    lines.push(format!("  Tickets: {}", tickets.join(", ")));
+    match &entry.preset {
+        Some(preset) => lines.push(format!("  Booked at {} with preset {preset}", entry.booked_at.format("%Y/%m/%d %H:%M:%S"))),
+        None => lines.push(format!("  Booked at {}", entry.booked_at.format("%Y/%m/%d %H:%M:%S"))),
+    }
+    lines.join("\n")
+}
+
+// Ex. NT$1,490
+fn format_price(price: u32) -> String {
+    let digits = price.to_string();
+    let mut formatted = String::new();
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx) % 3 == 0 {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    format!("NT${formatted}")
+}
@@ -0,0 +1,98 @@
+use crate::render::{emit, Event};
+use crate::App;
+use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz::Asia__Taipei;
+use clap::Args;
+use log::{debug, warn};
+use std::thread::sleep;
+use thsr_ticket_rs::clock::{estimate_offset, sleep_until};
+use thsr_ticket_rs::Result;
+
+// Spread the samples over different fractions of a second to narrow down the clock offset
+const CLOCK_SAMPLE_GAP_MS: u64 = 230;
+
+#[derive(Args, Debug)]
+pub struct ScheduleArgs {
+    /// Submit the booking at this time, ex. 2026-11-01T00:00:00+08:00 or "2026-11-01 00:00" in Taipei time
+    #[arg(long, value_parser = parse_at, help_heading = "Schedule")]
+    pub at: Option<DateTime<FixedOffset>>,
+
+    /// Seconds before --at to start the session and solve the CAPTCHA
+    #[arg(long, default_value_t = 60, requires = "at", help_heading = "Schedule")]
+    prepare_ahead: u64,
+
+    /// Number of requests to estimate the clock offset of the server with
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..), requires = "at", help_heading = "Schedule")]
+    clock_samples: u32,
+}
+
+/// Prepares the booking ahead of time and submits it once the server clock reaches the time
+pub fn run_book_at(mut app: App, at: DateTime<FixedOffset>, args: &ScheduleArgs) -> Result<()> {
+    debug!("app inited: {:?}", app);
+    let at = at.with_timezone(&Utc);
+    // Relative dates are as of the submission, ex. `latest` right after the booking window opens at midnight
+    app.booking_day = Some(at.with_timezone(&app.tz).date_naive());
+    app.prepare_preset()?;
+    // Get the questions out of the way while there's still time
+    app.prepare_worksheets()?;
+    if app.train_selection.is_none() {
+        say!("No train selection strategy given, the train will be asked for after submitting; consider --train-selection to save time");
+    }
+
+    let prepare_at = at - Duration::seconds(args.prepare_ahead as i64);
+    if prepare_at > Utc::now() {
+        say!("Waiting until {} to prepare", prepare_at.with_timezone(&app.tz).format("%Y/%m/%d %H:%M:%S"));
+        sleep_until(prepare_at);
+    }
+
+    // Also warms up the connection
+    let offset = estimate_clock_offset(&app, args.clock_samples)?;
+    let (session_id, booking) = app.prepare_search()?;
+    debug!("booking: {:?}", booking);
+
+    let submit_at = at - offset;
+    if submit_at < Utc::now() {
+        warn!("already past {at} by the server clock, submitting right away");
+    } else {
+        say!("Submitting at {}", at.with_timezone(&app.tz).format("%Y/%m/%d %H:%M:%S%.3f"));
+        emit(Event::Scheduled { submit_at: at.with_timezone(&app.tz).to_rfc3339() });
+        sleep_until(submit_at);
+    }
+    let (booking, booking_response) = app.submit_booking_with_captcha_retries(session_id, booking)?;
+    app.complete_booking(booking, booking_response)?;
+
+    Ok(())
+}
+
+// How far the server clock is ahead of the local clock, or zero if the server doesn't tell
+fn estimate_clock_offset(app: &App, samples: u32) -> Result<Duration> {
+    let mut clock_samples = Vec::new();
+    for i in 0..samples {
+        if i > 0 {
+            sleep(std::time::Duration::from_millis(CLOCK_SAMPLE_GAP_MS));
+        }
+        clock_samples.extend(app.client.sample_clock()?);
+    }
+    match estimate_offset(&clock_samples) {
+        Some(offset) => {
+            say!("Server clock is {}ms ahead of the local clock", offset.num_milliseconds());
+            Ok(offset)
+        },
+        None => {
+            warn!("the server doesn't tell its time, going by the local clock");
+            Ok(Duration::zero())
+        },
+    }
+}
+
+fn parse_at(at: &str) -> std::result::Result<DateTime<FixedOffset>, String> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(at) {
+        return Ok(at);
+    }
+    // Without an offset, it's in Taipei time like everything else on the site
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y/%m/%d %H:%M:%S", "%Y/%m/%d %H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(at, format).ok())
+        .and_then(|at| Asia__Taipei.from_local_datetime(&at).single())
+        .map(|at| at.fixed_offset())
+        .ok_or_else(|| "expecting a time like 2026-11-01T00:00:00+08:00 or 2026-11-01 00:00".to_string())
+}
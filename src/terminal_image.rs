@@ -0,0 +1,172 @@
+use crate::error::{Error, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Write;
+
+// Half-block rendering uses one column per pixel, so keep it narrow enough for most terminals
+const HALF_BLOCK_MAX_WIDTH: u32 = 80;
+// Kitty graphics protocol limits each chunk of the payload to 4096 bytes
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Ways of showing images inline in the terminal
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Protocol {
+    Kitty,
+    Iterm2,
+    Sixel,
+    // Unicode upper half blocks with 24-bit colors, which works on most terminals
+    HalfBlock,
+}
+
+/// Guesses the best protocol supported by the current terminal from the environment variables
+pub fn detect_protocol() -> Protocol {
+    let term = env::var("TERM").unwrap_or_default();
+    let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+    // iTerm2 sets `LC_TERMINAL`, which is usually forwarded over SSH unlike `TERM_PROGRAM`
+    let lc_terminal = env::var("LC_TERMINAL").unwrap_or_default();
+
+    if env::var_os("KITTY_WINDOW_ID").is_some() || term == "xterm-kitty" || term == "xterm-ghostty" || term_program == "ghostty" {
+        Protocol::Kitty
+    } else if term_program == "iTerm.app" || lc_terminal == "iTerm2" || term_program == "WezTerm" {
+        Protocol::Iterm2
+    } else if term.contains("sixel") || term == "foot" || term.starts_with("mlterm") || term_program == "mlterm" {
+        Protocol::Sixel
+    } else {
+        Protocol::HalfBlock
+    }
+}
+
+/// Renders the encoded image (PNG or JPEG) as a string to be printed to the terminal
+pub fn render(image: &[u8], protocol: Protocol) -> Result<String> {
+    match protocol {
+        // iTerm2 decodes the image itself
+        Protocol::Iterm2 => Ok(render_iterm2(image)),
+        _ => {
+            let decoded = image::load_from_memory(image)
+                .map_err(|err| Error::InvalidInput(format!("failed to decode image: {err}")))?
+                .to_rgba8();
+            Ok(match protocol {
+                Protocol::Kitty => render_kitty(&decoded),
+                Protocol::Sixel => render_sixel(&decoded),
+                _ => render_half_block(&decoded),
+            })
+        },
+    }
+}
+
+fn render_iterm2(image: &[u8]) -> String {
+    format!("\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07\n", image.len(), STANDARD.encode(image))
+}
+
+fn render_kitty(image: &RgbaImage) -> String {
+    let payload = STANDARD.encode(image.as_raw());
+    let chunks: Vec<&[u8]> = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    let mut output = String::new();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let more = if idx + 1 < chunks.len() { 1 } else { 0 };
+        // Only the first chunk carries the control data
+        let control = if idx == 0 {
+            format!("a=T,f=32,s={},v={},m={more}", image.width(), image.height())
+        } else {
+            format!("m={more}")
+        };
+        // Base64 is plain ASCII
+        let _ = write!(output, "\x1b_G{control};{}\x1b\\", String::from_utf8_lossy(chunk));
+    }
+    output.push('\n');
+    output
+}
+
+fn render_sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut output = format!("\x1bPq\"1;1;{width};{height}");
+
+    // Quantize to a 6x6x6 color cube which is small enough for every sixel terminal
+    let indices: Vec<u8> = image.pixels().map(|pixel| {
+        let [r, g, b] = flatten_alpha(pixel);
+        quantize(r) * 36 + quantize(g) * 6 + quantize(b)
+    }).collect();
+    let used_colors: BTreeSet<u8> = indices.iter().copied().collect();
+    for &color in &used_colors {
+        let level = |value: u8| value as u32 * 100 / 5;
+        let _ = write!(output, "#{color};2;{};{};{}", level(color / 36), level(color / 6 % 6), level(color % 6));
+    }
+
+    // Each band covers 6 rows of pixels
+    for band_top in (0..height).step_by(6) {
+        let band_colors: BTreeSet<u8> = (band_top..(band_top + 6).min(height))
+            .flat_map(|y| indices[(y * width) as usize..((y + 1) * width) as usize].iter().copied())
+            .collect();
+        for (idx, &color) in band_colors.iter().enumerate() {
+            if idx > 0 {
+                // Go back to the start of the band for the next color
+                output.push('$');
+            }
+            let _ = write!(output, "#{color}");
+            let sixels: Vec<char> = (0..width).map(|x| {
+                let bits = (0..6)
+                    .filter(|dy| band_top + dy < height && indices[((band_top + dy) * width + x) as usize] == color)
+                    .fold(0u8, |bits, dy| bits | (1 << dy));
+                (63 + bits) as char
+            }).collect();
+            push_run_length_encoded(&mut output, &sixels);
+        }
+        output.push('-');
+    }
+    output.push_str("\x1b\\\n");
+    output
+}
+
+fn push_run_length_encoded(output: &mut String, sixels: &[char]) {
+    let mut idx = 0;
+    while idx < sixels.len() {
+        let sixel = sixels[idx];
+        let run = sixels[idx..].iter().take_while(|&&other| other == sixel).count();
+        if run > 3 {
+            let _ = write!(output, "!{run}{sixel}");
+        } else {
+            (0..run).for_each(|_| output.push(sixel));
+        }
+        idx += run;
+    }
+}
+
+fn render_half_block(image: &RgbaImage) -> String {
+    let image = if image.width() > HALF_BLOCK_MAX_WIDTH {
+        let height = (image.height() * HALF_BLOCK_MAX_WIDTH / image.width()).max(1);
+        image::imageops::resize(image, HALF_BLOCK_MAX_WIDTH, height, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+
+    // Each character cell shows 2 pixels: the upper one in foreground and the lower one in background
+    let mut output = String::new();
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            let [top_r, top_g, top_b] = flatten_alpha(image.get_pixel(x, y));
+            let [bottom_r, bottom_g, bottom_b] = if y + 1 < image.height() {
+                flatten_alpha(image.get_pixel(x, y + 1))
+            } else {
+                [top_r, top_g, top_b]
+            };
+            let _ = write!(output, "\x1b[38;2;{top_r};{top_g};{top_b}m\x1b[48;2;{bottom_r};{bottom_g};{bottom_b}m\u{2580}");
+        }
+        output.push_str("\x1b[0m\n");
+    }
+    output
+}
+
+// Blends the pixel over a white background
+fn flatten_alpha(pixel: &Rgba<u8>) -> [u8; 3] {
+    let [r, g, b, a] = pixel.0;
+    let blend = |value: u8| ((value as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+    [blend(r), blend(g), blend(b)]
+}
+
+fn quantize(value: u8) -> u8 {
+    ((value as u32 * 5 + 127) / 255) as u8
+}
@@ -0,0 +1,113 @@
+use crate::error::{Error, Result};
+use crate::models::TrainInfo;
+use chrono::NaiveTime;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// How to pick a train from the list without asking.
+///
+/// Written as (case-insensitive):
+/// - `earliest`: the earliest departure
+/// - `fastest`: the shortest travel time
+/// - `discount`: the best discount
+/// - `prefer:803,805`: the first available train in the list, falling back to `earliest`;
+///   another fallback can be given after a slash, ex. `prefer:803,805/fastest`
+/// - `arrive-by:10:30`: the latest departure arriving at or before the time
+#[derive(Debug, PartialEq, Clone)]
+pub enum TrainSelectionStrategy {
+    Earliest,
+    Fastest,
+    BestDiscount,
+    Preferred {
+        train_ids: Vec<i16>,
+        fallback: Box<TrainSelectionStrategy>,
+    },
+    ArriveBy(NaiveTime),
+}
+
+impl TrainSelectionStrategy {
+    /// Picks the train from the list
+    pub fn select<'a>(&self, trains: &'a [TrainInfo]) -> Result<&'a TrainInfo> {
+        let selected = match self {
+            TrainSelectionStrategy::Earliest => trains.iter().min_by_key(|train| train.depart),
+            // Trains of the same travel time are ordered by departure
+            TrainSelectionStrategy::Fastest => trains.iter().min_by_key(|train| (train.travel_time, train.depart)),
+            TrainSelectionStrategy::BestDiscount => trains.iter().min_by_key(|train| (train.best_price_percentage(), train.depart)),
+            TrainSelectionStrategy::Preferred { train_ids, fallback } => {
+                match train_ids.iter().find_map(|train_id| trains.iter().find(|train| train.id == *train_id)) {
+                    Some(train) => Some(train),
+                    None => return fallback.select(trains),
+                }
+            },
+            TrainSelectionStrategy::ArriveBy(deadline) => trains.iter()
+                .filter(|train| train.arrive <= *deadline)
+                .max_by_key(|train| train.depart),
+        };
+        selected.ok_or_else(|| Error::NoMatchingTrain(format!("none of the {} train(s) matches {self}", trains.len())))
+    }
+}
+
+impl fmt::Display for TrainSelectionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrainSelectionStrategy::Earliest => write!(f, "earliest"),
+            TrainSelectionStrategy::Fastest => write!(f, "fastest"),
+            TrainSelectionStrategy::BestDiscount => write!(f, "discount"),
+            TrainSelectionStrategy::Preferred { train_ids, fallback } => {
+                let train_ids: Vec<String> = train_ids.iter().map(i16::to_string).collect();
+                write!(f, "prefer:{}", train_ids.join(","))?;
+                match fallback.as_ref() {
+                    TrainSelectionStrategy::Earliest => Ok(()),
+                    fallback => write!(f, "/{fallback}"),
+                }
+            },
+            TrainSelectionStrategy::ArriveBy(deadline) => write!(f, "arrive-by:{}", deadline.format("%H:%M")),
+        }
+    }
+}
+
+impl FromStr for TrainSelectionStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |message: &str| Error::InvalidInput(format!("invalid train selection {s:?}: {message}"));
+        let s = s.trim().to_lowercase();
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s.as_str(), None),
+        };
+        match (name, arg) {
+            ("earliest", None) => Ok(TrainSelectionStrategy::Earliest),
+            ("fastest", None) => Ok(TrainSelectionStrategy::Fastest),
+            ("discount", None) => Ok(TrainSelectionStrategy::BestDiscount),
+            ("prefer", Some(arg)) => {
+                let (train_ids, fallback) = match arg.split_once('/') {
+                    Some((train_ids, fallback)) => (train_ids, fallback.parse()?),
+                    None => (arg, TrainSelectionStrategy::Earliest),
+                };
+                let train_ids = train_ids.split(',')
+                    .map(|train_id| train_id.trim().parse().map_err(|_| invalid("expecting train IDs like 803,805")))
+                    .collect::<Result<Vec<i16>>>()?;
+                Ok(TrainSelectionStrategy::Preferred { train_ids, fallback: Box::new(fallback) })
+            },
+            ("arrive-by", Some(arg)) => NaiveTime::parse_from_str(arg.trim(), "%H:%M")
+                .map(TrainSelectionStrategy::ArriveBy)
+                .map_err(|_| invalid("expecting a time like 10:30")),
+            _ => Err(invalid("expecting earliest, fastest, discount, prefer:<train IDs> or arrive-by:<HH:MM>")),
+        }
+    }
+}
+
+// Saved as how it's written in presets
+impl Serialize for TrainSelectionStrategy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TrainSelectionStrategy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,150 @@
+use crate::render::{emit, Event};
+use crate::App;
+use clap::Args;
+use log::{debug, warn};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread::sleep;
+use std::time::Duration;
+use strum::{Display, EnumString};
+use thsr_ticket_rs::configs::CaptchaSolverKind;
+use thsr_ticket_rs::models::{Booking, BookingResponse, TrainInfo};
+use thsr_ticket_rs::train_selection::TrainSelectionStrategy;
+use thsr_ticket_rs::utils::{format_travel_time, shell_command};
+use thsr_ticket_rs::{Error, Result};
+
+// Polling more often than this only gets us blocked
+const MIN_INTERVAL_SECS: u64 = 10;
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Seconds between searches
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(MIN_INTERVAL_SECS..), help_heading = "Watch")]
+    interval: u64,
+
+    /// Randomize each interval by up to this fraction, ex. 0.2 for ±20%
+    #[arg(long, default_value_t = 0.2, help_heading = "Watch")]
+    jitter: f64,
+
+    /// Max seconds between searches when backing off from errors
+    #[arg(long, default_value_t = 600, help_heading = "Watch")]
+    max_interval: u64,
+
+    /// Give up after this many searches
+    #[arg(long, default_value_t = 60, help_heading = "Watch")]
+    max_polls: u32,
+
+    /// What to do once a matching train is available: book or notify
+    #[arg(long, default_value_t = OnMatch::Book, help_heading = "Watch")]
+    on_match: OnMatch,
+
+    /// Shell command to run once a matching train is available, with the message in `THSR_MESSAGE`
+    #[arg(long, help_heading = "Watch")]
+    notify_command: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum OnMatch {
+    Book,
+    Notify,
+}
+
+pub fn run_watch(mut app: App, args: &WatchArgs) -> Result<()> {
+    debug!("app inited: {:?}", app);
+    app.prepare_preset()?;
+    if app.config.captcha.solver == CaptchaSolverKind::Stdin {
+        warn!("every search asks for the answer to a new CAPTCHA, consider solving them with --captcha-solver command or http");
+    }
+    // Nobody is around to pick the train
+    if app.train_selection.is_none() {
+        say!("No train selection strategy given, any available train matches");
+        app.train_selection = Some(TrainSelectionStrategy::Earliest);
+    }
+
+    let mut failures = 0;
+    for poll in 1..=args.max_polls {
+        say!("Searching ({poll}/{})", args.max_polls);
+        emit(Event::Searching { poll });
+        let result = app.search_trains().and_then(|(booking, booking_response)| {
+            let message = describe_match(&app, &booking, &booking_response)?;
+            Ok((booking, booking_response, message))
+        });
+        match result {
+            Ok((booking, booking_response, message)) => {
+                say!("{message}");
+                emit(Event::Matched { message: &message });
+                notify(args, &message);
+                if args.on_match == OnMatch::Book {
+                    app.complete_booking(booking, booking_response)?;
+                }
+                return Ok(());
+            },
+            // Sold out or nothing matches (yet), while any other feedback of the server won't go away by searching again
+            Err(err) if matches!(err, Error::NoMatchingTrain(_)) || err.is_sold_out() => {
+                say!("{err}");
+                failures = 0;
+            },
+            // Most likely transient, so back off and try again
+            Err(err @ (Error::Network(_) | Error::MissingCookie(_))) => {
+                warn!("search failed: {err}");
+                failures += 1;
+            },
+            Err(err) => return Err(err),
+        }
+
+        if poll < args.max_polls {
+            let interval = next_interval(args, failures);
+            say!("Searching again in {}s", interval.as_secs());
+            sleep(interval);
+        }
+    }
+
+    Err(Error::NoMatchingTrain(format!("still not available after {} searches", args.max_polls)))
+}
+
+// Checks the trains against the strategies the same way they will be selected for booking
+fn describe_match(app: &App, booking: &Booking, booking_response: &BookingResponse) -> Result<String> {
+    let route = format!("{} -> {} on {}", booking.persisted.start_station, booking.persisted.dest_station, booking.persisted.outbound_date);
+    match booking_response {
+        BookingResponse::Trains(trains) => {
+            let strategy = app.train_selection.as_ref().expect("strategy is set before watching");
+            let mut message = format!("Available: {route}, {}", describe_train(strategy.select(&trains.outbound)?));
+            if !trains.inbound.is_empty() {
+                let strategy = app.return_train_selection.as_ref().unwrap_or(strategy);
+                message.push_str(&format!(", return {}", describe_train(strategy.select(&trains.inbound)?)));
+            }
+            Ok(message)
+        },
+        // Searching by train ID goes straight to the passenger form once the train is available
+        BookingResponse::TrainSelected(_) => Ok(format!("Available: {route}, train {}", booking.persisted.to_train_id.unwrap_or_default())),
+    }
+}
+
+fn describe_train(train: &TrainInfo) -> String {
+    format!("train {} ({} -> {}, {})", train.id, train.depart.format("%H:%M"), train.arrive.format("%H:%M"), format_travel_time(train.travel_time))
+}
+
+fn notify(args: &WatchArgs, message: &str) {
+    // Ring the terminal bell, without getting in the way of the machine-readable output
+    eprint!("\x07");
+    if let Some(command) = &args.notify_command {
+        match shell_command(command).env("THSR_MESSAGE", message).status() {
+            Ok(status) if !status.success() => warn!("notify command exited with {status}"),
+            Ok(_) => (),
+            Err(err) => warn!("failed to run the notify command: {err}"),
+        }
+    }
+}
+
+// Doubles the interval for each consecutive failure, then randomizes it so the searches don't look scripted
+fn next_interval(args: &WatchArgs, failures: u32) -> Duration {
+    let backoff = args.interval.saturating_mul(1 << failures.min(16)).min(args.max_interval.max(args.interval));
+    let jitter = args.jitter.clamp(0.0, 1.0) * (random_fraction() * 2.0 - 1.0);
+    Duration::from_secs_f64((backoff as f64 * (1.0 + jitter)).max(MIN_INTERVAL_SECS as f64))
+}
+
+// Between 0 and 1; the hasher is randomly seeded for each instance, which is good enough for jitter
+fn random_fraction() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
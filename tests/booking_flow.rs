@@ -0,0 +1,479 @@
+//! Runs the whole booking flow of the binary against the mock server, answering the prompts through stdin
+
+use chrono::Days;
+use chrono_tz::Tz::Asia__Taipei;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thsr_ticket_rs::dates::today;
+use thsr_ticket_rs::history::load_history;
+use thsr_ticket_rs::mock_server::{MockServer, Page, RecordedRequest, Scenario};
+use thsr_ticket_rs::models::{Station, Trip};
+use thsr_ticket_rs::presets::load_presets;
+use thsr_ticket_rs::utils::format_date;
+
+const PRESET: &str = r#"{
+  "version": 2,
+  "presets": [
+    {
+      "name": "family",
+      "from": "Taipei",
+      "to": "Tainan",
+      "trip": "OneWay",
+      "date": "+3d",
+      "time": "09:30",
+      "class": "Standard",
+      "seat": "Window",
+      "tickets": {
+        "adult": 1,
+        "child": 1,
+        "disabled": 1,
+        "elder": 2,
+        "college": 1
+      },
+      "personal_id": "A111111111",
+      "phone": "0912345678",
+      "passengers": [
+        { "type": "disabled", "id": "A222222222" },
+        { "type": "elder", "id": "A333333333" },
+        { "type": "elder", "id": "A444444444" }
+      ]
+    }
+  ]
+}"#;
+
+/// Files of a run, removed afterwards
+struct Workspace {
+    dir: PathBuf,
+}
+
+impl Workspace {
+    fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!("thsr-ticket-rs-test-{}-{}", std::process::id(), COUNT.fetch_add(1, Ordering::SeqCst)));
+        fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_string_lossy().into_owned()
+    }
+
+    /// Runs the binary with the answers on stdin, solving the CAPTCHA with a command
+    fn run(&self, server: &MockServer, args: &[&str], answers: &str) -> Output {
+        self.run_with_solver(server, &["--captcha-solver", "command", "--captcha-command", "echo ABCD"], args, answers)
+    }
+
+    fn run_with_solver(&self, server: &MockServer, solver_args: &[&str], args: &[&str], answers: &str) -> Output {
+        self.run_command(server, &[], solver_args, args, answers)
+    }
+
+    /// Runs the command of the binary (ex. `watch`), where the options go after it
+    fn run_command(&self, server: &MockServer, command: &[&str], solver_args: &[&str], args: &[&str], answers: &str) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_thsr-ticket-rs"))
+            .args(command)
+            .args(["--presets-file", &self.path("presets.json"), "--history-file", &self.path("history.jsonl"), "--config", &self.path("config.json")])
+            .args(solver_args)
+            .args(args)
+            .env("THSR_BASE_URL", server.base_url())
+            .current_dir(&self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // The binary may fail before reading all the answers, which the output tells
+        let _ = child.stdin.take().unwrap().write_all(answers.as_bytes());
+        let output = child.wait_with_output().unwrap();
+        println!("stdout:\n{}", String::from_utf8_lossy(&output.stdout));
+        println!("stderr:\n{}", String::from_utf8_lossy(&output.stderr));
+        output
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// The requests posting the form of the page, ex. `BookingS1Form`
+fn posted<'a>(requests: &'a [RecordedRequest], form: &str) -> Vec<&'a RecordedRequest> {
+    requests.iter().filter(|request| request.method == "POST" && request.target.contains(&format!("{form}::IFormSubmitListener"))).collect()
+}
+
+// The form as a map, failing if any key is posted twice
+fn form_of(request: &RecordedRequest) -> BTreeMap<&str, &str> {
+    let form: BTreeMap<&str, &str> = request.form.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+    assert_eq!(form.len(), request.form.len(), "duplicate keys in {:?}", request.form);
+    form
+}
+
+fn days_later(days: u64) -> String {
+    format_date(today(&Asia__Taipei).checked_add_days(Days::new(days)).unwrap())
+}
+
+fn supplemental_id_key(ticket_idx: u8) -> String {
+    format!("TicketPassengerInfoInputPanel:passengerDataView:{ticket_idx}:passengerDataView2:passengerDataIdNumber")
+}
+
+#[test]
+fn books_with_the_preset_selected_and_the_train_picked_on_stdin() {
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET).unwrap();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    // Preset #1, then the second train
+    let output = workspace.run(&server, &[], "1\n2\n");
+    assert!(output.status.success());
+
+    let requests = server.requests();
+    let booking_forms = posted(&requests, "BookingS1Form");
+    assert_eq!(booking_forms.len(), 1);
+    assert_eq!(form_of(booking_forms[0]), BTreeMap::from([
+        ("selectStartStation", "2"),
+        ("selectDestinationStation", "11"),
+        ("tripCon:typesoftrip", "0"),
+        ("toTimeInputField", days_later(3).as_str()),
+        ("toTimeTable", "930A"),
+        ("seatCon:seatRadioGroup", "1"),
+        ("trainCon:trainRadioGroup", "0"),
+        ("ticketPanel:rows:0:ticketAmount", "1F"),
+        ("ticketPanel:rows:1:ticketAmount", "1H"),
+        ("ticketPanel:rows:2:ticketAmount", "1W"),
+        ("ticketPanel:rows:3:ticketAmount", "2E"),
+        ("ticketPanel:rows:4:ticketAmount", "1P"),
+        ("bookingMethod", "radio31"),
+        ("homeCaptcha:securityCode", "ABCD"),
+        ("BookingS1Form:hf:0", ""),
+    ]));
+
+    let train_forms = posted(&requests, "BookingS2Form");
+    assert_eq!(train_forms.len(), 1);
+    assert_eq!(form_of(train_forms[0]), BTreeMap::from([
+        ("TrainQueryDataViewPanel:TrainGroup", "radio20"),
+        ("BookingS2Form:hf:0", ""),
+    ]));
+
+    // The IDs follow the adult and child tickets, which don't need any
+    let ticket_forms = posted(&requests, "BookingS3Form");
+    assert_eq!(ticket_forms.len(), 1);
+    let (disabled_key, elder_key, second_elder_key) = (supplemental_id_key(2), supplemental_id_key(3), supplemental_id_key(4));
+    assert_eq!(form_of(ticket_forms[0]), BTreeMap::from([
+        ("dummyId", "A111111111"),
+        ("dummyPhone", "0912345678"),
+        (disabled_key.as_str(), "A222222222"),
+        (elder_key.as_str(), "A333333333"),
+        (second_elder_key.as_str(), "A444444444"),
+        ("TicketMemberSystemInputPanel:TakerMemberSystemDataView:memberSystemRadioGroup", "radio44"),
+        ("BookingS3FormSP:hf:0", ""),
+        ("idInputRadio", "0"),
+        ("diffOver", "1"),
+        ("email", ""),
+        ("agree", "on"),
+        ("isGoBackM", ""),
+        ("backHome", ""),
+        ("TgoError", "1"),
+    ]));
+
+    let history = load_history(&workspace.path("history.jsonl")).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].preset.as_deref(), Some("family"));
+    assert_eq!(history[0].result.pnr, "01234567");
+    assert_eq!(history[0].result.legs[0].train, 805);
+    assert_eq!(history[0].result.seats.len(), 6);
+}
+
+#[test]
+fn books_a_round_trip_answered_on_stdin() {
+    let workspace = Workspace::new();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    let answers = [
+        // Stations, round trip, by time
+        "7", "Zuoying", "1", "0",
+        // Departure date and time (800A), return date and time (600P)
+        "+3d", "9", "+5d", "16",
+        // Aisle, business class
+        "2", "1",
+        // 2 adult, 0 child, 0 disabled, 1 elder, 0 college tickets
+        "2", "", "", "1", "",
+        // The third departure train and the first return train
+        "3", "",
+        // Personal ID, phone, and the ID of the elder
+        "A111111111", "", "A333333333",
+    ];
+    let output = workspace.run(&server, &[], &format!("{}\n", answers.join("\n")));
+    assert!(output.status.success());
+
+    let requests = server.requests();
+    let booking_forms = posted(&requests, "BookingS1Form");
+    assert_eq!(booking_forms.len(), 1);
+    assert_eq!(form_of(booking_forms[0]), BTreeMap::from([
+        ("selectStartStation", "7"),
+        ("selectDestinationStation", "12"),
+        ("tripCon:typesoftrip", "1"),
+        ("toTimeInputField", days_later(3).as_str()),
+        ("toTimeTable", "800A"),
+        ("backTimeInputField", days_later(5).as_str()),
+        ("backTimeTable", "600P"),
+        ("seatCon:seatRadioGroup", "2"),
+        ("trainCon:trainRadioGroup", "1"),
+        ("ticketPanel:rows:0:ticketAmount", "2F"),
+        ("ticketPanel:rows:1:ticketAmount", "0H"),
+        ("ticketPanel:rows:2:ticketAmount", "0W"),
+        ("ticketPanel:rows:3:ticketAmount", "1E"),
+        ("ticketPanel:rows:4:ticketAmount", "0P"),
+        ("bookingMethod", "radio31"),
+        ("homeCaptcha:securityCode", "ABCD"),
+        ("BookingS1Form:hf:0", ""),
+    ]));
+
+    let train_forms = posted(&requests, "BookingS2Form");
+    assert_eq!(train_forms.len(), 1);
+    assert_eq!(form_of(train_forms[0]), BTreeMap::from([
+        ("TrainQueryDataViewPanel:TrainGroup", "radio22"),
+        ("TrainQueryDataViewPanel2:TrainGroup", "radio26"),
+        ("BookingS2Form:hf:0", ""),
+    ]));
+
+    let ticket_forms = posted(&requests, "BookingS3Form");
+    assert_eq!(ticket_forms.len(), 1);
+    let form = form_of(ticket_forms[0]);
+    assert_eq!(form.get("dummyId"), Some(&"A111111111"));
+    assert_eq!(form.get("dummyPhone"), Some(&""));
+    assert_eq!(form.get(supplemental_id_key(2).as_str()), Some(&"A333333333"));
+    assert_eq!(form.keys().filter(|key| key.contains("passengerDataIdNumber")).count(), 1);
+
+    let history = load_history(&workspace.path("history.jsonl")).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].preset, None);
+    let trains: Vec<i16> = history[0].result.legs.iter().map(|leg| leg.train).collect();
+    assert_eq!(trains, [1503, 652]);
+}
+
+#[test]
+fn keeps_the_return_of_the_preset_when_only_the_date_is_given() {
+    let workspace = Workspace::new();
+    let preset = PRESET
+        .replace(r#""trip": "OneWay","#, r#""trip": "RoundTrip", "return_date": "+5d", "return_time": "18:00","#);
+    fs::write(workspace.path("presets.json"), preset).unwrap();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    let output = workspace.run(&server, &["-p", "family", "--date", "+4d", "--train-selection", "earliest"], "");
+    assert!(output.status.success());
+
+    let requests = server.requests();
+    let form = form_of(posted(&requests, "BookingS1Form")[0]);
+    assert_eq!(form.get("tripCon:typesoftrip"), Some(&"1"));
+    assert_eq!(form.get("toTimeInputField"), Some(&days_later(4).as_str()));
+    assert_eq!(form.get("backTimeInputField"), Some(&days_later(5).as_str()));
+    assert_eq!(form.get("backTimeTable"), Some(&"600P"));
+    assert_eq!(load_history(&workspace.path("history.jsonl")).unwrap()[0].result.legs.len(), 2);
+
+    // Nor is the return lost when editing the preset
+    let status = Command::new(env!("CARGO_BIN_EXE_thsr-ticket-rs"))
+        .args(["presets", "edit", "family", "--date", "+4d", "--presets-file", &workspace.path("presets.json")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let presets = load_presets(&workspace.path("presets.json")).unwrap().unwrap();
+    assert_eq!(presets[0].booking.types_of_trip, Trip::RoundTrip);
+    assert_eq!(presets[0].booking.outbound_date, "+4d");
+    assert_eq!(presets[0].booking.inbound_date.as_deref(), Some("+5d"));
+}
+
+#[test]
+fn rejects_times_off_the_time_table() {
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET.replace(r#""time": "09:30""#, r#""time": "09:15""#)).unwrap();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    let output = workspace.run(&server, &["--time", "09:15"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("try 09:00 or 09:30"));
+
+    let output = workspace.run(&server, &["-p", "family"], "");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("try 09:00 or 09:30"));
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn rejects_preset_dates_out_of_range() {
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET.replace(r#""date": "+3d""#, r#""date": "+99999999999d""#)).unwrap();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    let output = workspace.run(&server, &["-p", "family", "--train-selection", "earliest"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out of range"));
+    assert!(posted(&server.requests(), "BookingS1Form").is_empty());
+}
+
+#[test]
+fn searches_by_train_id_without_the_train_list() {
+    let workspace = Workspace::new();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    let args = ["--from", "Taipei", "--to", "Zuoying", "--date", "+3d", "--train", "609", "--adult", "1", "--class", "Standard", "--seat", "NoPref", "--id", "A111111111", "--phone", ""];
+    let output = workspace.run(&server, &args, "");
+    assert!(output.status.success());
+
+    let requests = server.requests();
+    let form = form_of(posted(&requests, "BookingS1Form")[0]);
+    assert_eq!(form.get("bookingMethod"), Some(&"radio33"));
+    assert_eq!(form.get("toTrainIDInputField"), Some(&"609"));
+    assert_eq!(form.get("ticketPanel:rows:0:ticketAmount"), Some(&"1F"));
+    assert!(posted(&requests, "BookingS2Form").is_empty());
+    assert_eq!(posted(&requests, "BookingS3Form").len(), 1);
+}
+
+#[test]
+fn retries_a_wrong_captcha_in_a_new_session() {
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET).unwrap();
+    let server = MockServer::start(vec![Scenario::WrongCaptcha]).unwrap();
+
+    let output = workspace.run(&server, &["-p", "family", "--train-selection", "earliest"], "");
+    assert!(output.status.success());
+
+    let requests = server.requests();
+    let booking_forms = posted(&requests, "BookingS1Form");
+    assert_eq!(booking_forms.len(), 2);
+    // Resubmitted as it was in the session of the new CAPTCHA
+    assert_ne!(booking_forms[0].target, booking_forms[1].target);
+    assert_eq!(form_of(booking_forms[0]), form_of(booking_forms[1]));
+    assert_eq!(posted(&requests, "BookingS3Form").len(), 1);
+}
+
+#[test]
+fn exits_with_the_code_of_the_failure() {
+    let cases = [
+        (Scenario::SoldOut, 5),
+        (Scenario::Maintenance, 4),
+        (Scenario::Malformed(Page::Booking), 6),
+        (Scenario::Malformed(Page::Trains), 6),
+        (Scenario::Malformed(Page::Passengers), 6),
+        (Scenario::Malformed(Page::Confirmation), 6),
+    ];
+    for (scenario, exit_code) in cases {
+        let workspace = Workspace::new();
+        fs::write(workspace.path("presets.json"), PRESET).unwrap();
+        let server = MockServer::start(vec![scenario.clone()]).unwrap();
+
+        let output = workspace.run(&server, &["-p", "1", "--train-selection", "fastest"], "");
+        assert_eq!(output.status.code(), Some(exit_code), "{scenario}");
+        // Nothing is recorded unless booked
+        assert!(load_history(&workspace.path("history.jsonl")).unwrap().is_empty(), "{scenario}");
+    }
+}
+
+#[test]
+fn watches_only_while_sold_out() {
+    let solver_args = ["--captcha-solver", "command", "--captcha-command", "echo ABCD"];
+    let args = ["-p", "1", "--max-polls", "1"];
+
+    // Searched again later, until out of polls
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET).unwrap();
+    let server = MockServer::start(vec![Scenario::SoldOut]).unwrap();
+    let output = workspace.run_command(&server, &["watch"], &solver_args, &args, "");
+    assert_eq!(output.status.code(), Some(9));
+
+    // Any other feedback fails right away
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET).unwrap();
+    let server = MockServer::start(vec![Scenario::WrongCaptcha]).unwrap();
+    let output = workspace.run_command(&server, &["watch"], &solver_args, &[&args[..], &["--captcha-attempts", "1"]].concat(), "");
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("檢測碼"));
+}
+
+#[test]
+fn keeps_the_json_output_parseable_when_the_captcha_is_typed() {
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET).unwrap();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    // The image and the question go to stderr, with the answer on stdin
+    let solver_args = ["--captcha-solver", "stdin", "--captcha-display", "half-block"];
+    let output = workspace.run_with_solver(&server, &solver_args, &["-p", "family", "--train-selection", "earliest", "--output", "json"], "ABCD\n");
+    assert!(output.status.success());
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(document["result"]["pnr"], "01234567");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Type the answer to the CAPTCHA:"));
+}
+
+#[test]
+fn books_with_the_answers_file() {
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET).unwrap();
+    fs::write(workspace.path("answers.txt"), "# The preset by name\nfamily\n# The second train\n2\n").unwrap();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    // Nothing is read from stdin
+    let output = workspace.run(&server, &["--answers", &workspace.path("answers.txt")], "");
+    assert!(output.status.success());
+
+    let history = load_history(&workspace.path("history.jsonl")).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].preset.as_deref(), Some("family"));
+    assert_eq!(history[0].result.legs[0].train, 805);
+}
+
+#[test]
+fn adds_and_edits_presets_with_the_answers_file() {
+    let workspace = Workspace::new();
+    let answers = [
+        // Taipei to Tainan, one way by time, 3 days later at 08:30
+        "2", "Tainan", "0", "0", "+3d", "10",
+        // Window, standard class, 1 adult ticket only
+        "1", "0", "1", "", "", "", "",
+        "A111111111", "0912345678",
+    ];
+    fs::write(workspace.path("answers.txt"), format!("# Added by the test\n{}\n", answers.join("\n"))).unwrap();
+    let presets = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_thsr-ticket-rs"))
+        .args(["presets", "--presets-file", &workspace.path("presets.json")])
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(presets(&["add", "--name", "work", "--answers", &workspace.path("answers.txt")]).status.success());
+    let saved = load_presets(&workspace.path("presets.json")).unwrap().unwrap();
+    assert_eq!(saved[0].booking.dest_station, Station::Tainan);
+    assert_eq!(saved[0].booking.outbound_time, "830A");
+    assert_eq!(saved[0].ticket_confirmation.phone_num, "0912345678");
+
+    // Editing asks the same questions
+    let output = presets(&["edit", "work", "--unattended"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("asked while unattended"));
+
+    // A mistyped answer tells which one
+    fs::write(workspace.path("answers.txt"), "2\nTainan\nx1\n").unwrap();
+    let output = presets(&["add", "--answers", &workspace.path("answers.txt")]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(r#"invalid trip type "x1""#));
+}
+
+#[test]
+fn fails_when_asked_while_unattended() {
+    let workspace = Workspace::new();
+    fs::write(workspace.path("presets.json"), PRESET).unwrap();
+    let server = MockServer::start(Vec::new()).unwrap();
+
+    // The train is left to be picked, while the answers on stdin are ignored
+    let output = workspace.run(&server, &["-p", "family", "--unattended"], "1\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("asked while unattended"));
+    assert!(posted(&server.requests(), "BookingS2Form").is_empty());
+
+    let output = workspace.run(&server, &["-p", "family", "--unattended", "--train-selection", "earliest"], "");
+    assert!(output.status.success());
+    assert_eq!(load_history(&workspace.path("history.jsonl")).unwrap()[0].result.legs[0].train, 803);
+}
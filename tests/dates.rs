@@ -0,0 +1,84 @@
+//! Checks the grammar of the dates given either as-is or relative to today
+
+use chrono::NaiveDate;
+use thsr_ticket_rs::dates::DateExpr;
+use thsr_ticket_rs::Error;
+
+// A Monday
+fn today() -> NaiveDate {
+    date(2025, 10, 20)
+}
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+fn resolve(expr: &str) -> NaiveDate {
+    DateExpr::parse(expr).unwrap().resolve(today()).unwrap()
+}
+
+fn assert_invalid<T>(result: Result<T, Error>, expected_message: &str) {
+    match result {
+        Err(err @ Error::InvalidInput(_)) => assert!(err.to_string().contains(expected_message), "{err}"),
+        Err(err) => panic!("expecting invalid input, got {err}"),
+        Ok(_) => panic!("expecting invalid input"),
+    }
+}
+
+#[test]
+fn resolves_fixed_dates() {
+    assert_eq!(resolve("2025/01/04"), date(2025, 1, 4));
+    assert!(DateExpr::parse("2025/01/04").unwrap().is_fixed());
+    assert!(!DateExpr::parse("today").unwrap().is_fixed());
+}
+
+#[test]
+fn resolves_named_dates() {
+    assert_eq!(resolve("today"), today());
+    assert_eq!(resolve("tomorrow"), date(2025, 10, 21));
+    assert_eq!(resolve("latest"), date(2025, 11, 19));
+    assert_eq!(resolve(" Latest Bookable "), date(2025, 11, 19));
+}
+
+#[test]
+fn resolves_offsets() {
+    assert_eq!(resolve("+27d"), date(2025, 11, 16));
+    assert_eq!(resolve("today+28"), date(2025, 11, 17));
+    assert_eq!(resolve("latest-1w"), date(2025, 11, 12));
+    assert_eq!(resolve("tomorrow + 1w - 2d"), date(2025, 10, 26));
+    assert_eq!(resolve("-3"), date(2025, 10, 17));
+}
+
+#[test]
+fn resolves_days_of_week() {
+    assert_eq!(resolve("next friday"), date(2025, 10, 24));
+    // Never today itself
+    assert_eq!(resolve("next monday"), date(2025, 10, 27));
+    assert_eq!(resolve("next Sat"), date(2025, 10, 25));
+    assert_eq!(resolve("first weekday after today+28"), date(2025, 11, 18));
+    assert_eq!(resolve("first weekend after 2025/10/24"), date(2025, 10, 25));
+    assert_eq!(resolve("first weekday after 2025/10/24"), date(2025, 10, 27));
+    assert_eq!(resolve("first sunday after latest-1w"), date(2025, 11, 16));
+}
+
+#[test]
+fn rejects_bad_input() {
+    assert_invalid(DateExpr::parse("someday"), r#"unknown date "someday""#);
+    assert_invalid(DateExpr::parse("2025/13/01"), "unknown date");
+    assert_invalid(DateExpr::parse("next payday"), r#"unknown day "payday""#);
+    assert_invalid(DateExpr::parse("first friday"), "expecting `first <day> after <date>`");
+    assert_invalid(DateExpr::parse("first friday after someday"), r#"unknown date "someday""#);
+    assert_invalid(DateExpr::parse("+d"), "expecting a number");
+    assert_invalid(DateExpr::parse("today+1y"), "expecting + or - before");
+}
+
+#[test]
+fn rejects_dates_out_of_range() {
+    assert_invalid(DateExpr::parse("+99999999999999999999d"), "expecting a number");
+    assert_invalid(DateExpr::parse("+2000000000000000000w"), "too large");
+    assert_invalid(DateExpr::parse("+9223372036854775807d+1d"), "too large");
+    assert_invalid(DateExpr::parse("+99999999999d").unwrap().resolve(today()), "out of range");
+    assert_invalid(DateExpr::parse("-99999999999d").unwrap().resolve(today()), "out of range");
+    assert_invalid(DateExpr::parse("first friday after +99999999999d").unwrap().resolve(today()), "out of range");
+    assert_invalid(DateExpr::parse("next friday").unwrap().resolve(NaiveDate::MAX), "out of range");
+}
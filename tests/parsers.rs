@@ -0,0 +1,172 @@
+//! Checks the page parsers against saved pages of the booking site in `tests/fixtures`
+
+use chrono::{Duration, NaiveDate, NaiveTime};
+use scraper::Selector;
+use thsr_ticket_rs::configs::Endpoints;
+use thsr_ticket_rs::models::{BookingResponse, BookingResult, CabinClass, Discount, Seat, Station, TicketLeg, TrainInfo};
+use thsr_ticket_rs::parsers::{parse_booking_page, parse_confirmation_page, parse_passengers_page, parse_trains_page};
+use thsr_ticket_rs::Error;
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+fn time(hour: u32, minute: u32) -> NaiveTime {
+    NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+}
+
+fn seat(car: u8, number: &str) -> Seat {
+    Seat { car, number: number.to_string() }
+}
+
+// The trains of each leg of the train list
+fn trains_of(html: &str) -> (Vec<TrainInfo>, Vec<TrainInfo>) {
+    match parse_trains_page(html).unwrap() {
+        BookingResponse::Trains(trains) => (trains.outbound, trains.inbound),
+        BookingResponse::TrainSelected(_) => panic!("expecting the train list"),
+    }
+}
+
+// Ex. `803 06:26 08:00 1:34 radio18`
+fn describe(train: &TrainInfo) -> String {
+    format!("{} {} {} {}:{:02} {}", train.id, train.depart.format("%H:%M"), train.arrive.format("%H:%M"), train.travel_time.num_hours(), train.travel_time.num_minutes() % 60, train.form_value)
+}
+
+fn assert_layout_changed<T>(result: Result<T, Error>, expected_selector: &str) {
+    match result {
+        Err(Error::LayoutChanged { selector }) => {
+            assert_eq!(selector, expected_selector);
+            // So it can be tried out on the page
+            assert!(Selector::parse(&selector).is_ok(), "{selector} isn't a valid selector");
+        },
+        Err(err) => panic!("expecting the layout to have changed, got {err}"),
+        Ok(_) => panic!("expecting the layout to have changed"),
+    }
+}
+
+fn assert_feedback<T>(result: Result<T, Error>, expected_feedback: &str, wrong_captcha: bool) {
+    match result {
+        Err(err @ Error::ServerFeedback(_)) => {
+            assert!(err.to_string().contains(expected_feedback), "{err}");
+            assert_eq!(err.is_wrong_captcha(), wrong_captcha);
+        },
+        Err(err) => panic!("expecting the feedback of the site, got {err}"),
+        Ok(_) => panic!("expecting the feedback of the site"),
+    }
+}
+
+#[test]
+fn parses_booking_page() {
+    let endpoints = Endpoints::new("http://127.0.0.1:8080").unwrap();
+    let params = parse_booking_page(include_str!("fixtures/booking_page.html"), "5F6A1D2E".to_string(), &endpoints).unwrap();
+    assert_eq!(params.session_id, "5F6A1D2E");
+    assert_eq!(params.search_by_time_value, "radio31");
+    assert_eq!(params.search_by_train_id_value, "radio33");
+    // The placeholder is selected
+    assert_eq!(params.time_options, ["1201A", "1230A", "500A", "530A", "600A", "630A", "700A", "1200N", "1230P", "1130P"]);
+    assert_eq!(params.captcha_url, "http://127.0.0.1:8080/IMINT/?wicket:interface=:0:BookingS1Form:homeCaptcha:passCode::IResourceListener&wicket:antiCache=1730000000000");
+}
+
+#[test]
+fn parses_trains() {
+    let (outbound, inbound) = trains_of(include_str!("fixtures/trains.html"));
+    let outbound: Vec<String> = outbound.iter().map(describe).collect();
+    assert_eq!(outbound, ["803 06:26 08:00 1:34 radio18", "1505 06:50 08:45 1:55 radio20", "805 07:00 08:40 1:40 radio22"]);
+    assert!(inbound.is_empty());
+}
+
+#[test]
+fn parses_early_bird_discounts_of_both_legs() {
+    let (outbound, inbound) = trains_of(include_str!("fixtures/trains_early_bird.html"));
+    let discounts: Vec<Vec<Discount>> = outbound.iter().map(|train| train.discounts.clone()).collect();
+    assert_eq!(discounts, [vec![Discount::EarlyBird(65)], vec![Discount::EarlyBird(80)], vec![Discount::EarlyBird(90)]]);
+
+    let inbound: Vec<String> = inbound.iter().map(describe).collect();
+    assert_eq!(inbound, ["652 18:11 19:45 1:34 radio36", "1660 18:30 20:26 1:56 radio38"]);
+}
+
+#[test]
+fn parses_student_discounts() {
+    let (outbound, _) = trains_of(include_str!("fixtures/trains_student.html"));
+    let discounts: Vec<Vec<Discount>> = outbound.iter().map(|train| train.discounts.clone()).collect();
+    assert_eq!(discounts, [vec![Discount::College(50)], vec![Discount::EarlyBird(80), Discount::College(88)], vec![Discount::College(75)]]);
+    assert_eq!(outbound[1].best_price_percentage(), 80);
+    assert_eq!(outbound[0].travel_time, Duration::minutes(116));
+}
+
+#[test]
+fn parses_passenger_form() {
+    let html = include_str!("fixtures/passengers.html");
+    assert_eq!(parse_passengers_page(html).unwrap().member_value, "radio44");
+    // Searching by train ID skips the train list
+    match parse_trains_page(html).unwrap() {
+        BookingResponse::TrainSelected(params) => assert_eq!(params.member_value, "radio44"),
+        BookingResponse::Trains(_) => panic!("expecting the passenger form"),
+    }
+}
+
+#[test]
+fn parses_confirmation() {
+    let result = parse_confirmation_page(include_str!("fixtures/confirmation.html"), date(2025, 10, 20)).unwrap();
+    assert_eq!(result, BookingResult {
+        pnr: "05020716".to_string(),
+        total_price: 1490,
+        legs: vec![TicketLeg { date: date(2025, 11, 1), from: Station::Taipei, dest: Station::Zuoying, depart: time(6, 26), arrive: time(8, 0), train: 803 }],
+        class: CabinClass::Standard,
+        seats: vec![seat(5, "12A")],
+    });
+}
+
+#[test]
+fn parses_confirmation_of_multiple_seats_across_the_year() {
+    let result = parse_confirmation_page(include_str!("fixtures/confirmation_multiple_seats.html"), date(2025, 12, 3)).unwrap();
+    assert_eq!(result, BookingResult {
+        pnr: "05131442".to_string(),
+        total_price: 4050,
+        legs: vec![
+            TicketLeg { date: date(2025, 12, 30), from: Station::Nangang, dest: Station::Taichung, depart: time(8, 46), arrive: time(9, 52), train: 609 },
+            TicketLeg { date: date(2026, 1, 2), from: Station::Taichung, dest: Station::Nangang, depart: time(18, 11), arrive: time(19, 16), train: 652 },
+        ],
+        class: CabinClass::Standard,
+        seats: vec![seat(7, "5A"), seat(7, "5B"), seat(7, "5C"), seat(9, "1D"), seat(9, "1E"), seat(9, "2E")],
+    });
+}
+
+#[test]
+fn parses_confirmation_of_business_class() {
+    let result = parse_confirmation_page(include_str!("fixtures/confirmation_business.html"), date(2025, 10, 20)).unwrap();
+    assert_eq!(result.class, CabinClass::Business);
+    assert_eq!(result.total_price, 4160);
+    assert_eq!(result.seats, [seat(6, "3A"), seat(6, "3B")]);
+    assert_eq!(result.legs[0].dest, Station::Tainan);
+}
+
+#[test]
+fn reports_wrong_captcha() {
+    let html = include_str!("fixtures/error_wrong_captcha.html");
+    assert_feedback(parse_trains_page(html), "檢測碼輸入錯誤", true);
+}
+
+#[test]
+fn reports_sold_out() {
+    let html = include_str!("fixtures/error_sold_out.html");
+    assert_feedback(parse_trains_page(html), "去程查無可售車次", false);
+    assert_feedback(parse_passengers_page(html), "去程查無可售車次", false);
+    assert_feedback(parse_confirmation_page(html, date(2025, 10, 20)), "去程查無可售車次", false);
+}
+
+#[test]
+fn reports_layout_changes() {
+    let html = include_str!("fixtures/maintenance.html");
+    let endpoints = Endpoints::default();
+    assert_layout_changed(parse_booking_page(html, String::new(), &endpoints), r#"input[name="bookingMethod"][data-target="search-by-time"]"#);
+    assert_layout_changed(parse_trains_page(html), r#"label input[name="TrainQueryDataViewPanel:TrainGroup"]"#);
+    assert_layout_changed(parse_passengers_page(html), r#"input[name="TicketMemberSystemInputPanel:TakerMemberSystemDataView:memberSystemRadioGroup"][checked]"#);
+    assert_layout_changed(parse_confirmation_page(html, date(2025, 10, 20)), "#setTrainCode0");
+
+    // A page no longer matching a single selector
+    let trains = include_str!("fixtures/trains.html").replace(r#"<span class="duration">"#, r#"<span class="travel-time">"#);
+    assert_layout_changed(parse_trains_page(&trains), ".duration > span:nth-of-type(2)");
+    let confirmation = include_str!("fixtures/confirmation.html").replace(r#"<p class="info-title">車廂</p>"#, r#"<p class="info-title">座位</p>"#);
+    assert_layout_changed(parse_confirmation_page(&confirmation, date(2025, 10, 20)), "p.info-title + * span");
+}